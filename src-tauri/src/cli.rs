@@ -0,0 +1,579 @@
+use crate::{config, deep_link, fs_util, session};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(name = "velt")]
+#[command(version = env!("CARGO_PKG_VERSION"))]
+#[command(about = "A modern, minimal text editor", long_about = None)]
+pub struct Cli {
+    /// File(s) to open, optionally at a position (FILE:LINE[:COLUMN]), or
+    /// velt://open?path=...&line=...&column=... links; `-` reads stdin
+    #[arg(value_name = "FILE")]
+    pub files: Vec<String>,
+
+    /// Line to put the cursor on, for files given without a position
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub line: Option<u32>,
+
+    /// Column to put the cursor on (requires --line)
+    #[arg(long, value_name = "N", requires = "line", value_parser = clap::value_parser!(u32).range(1..))]
+    pub column: Option<u32>,
+
+    /// Open the files read-only
+    #[arg(long)]
+    pub readonly: bool,
+
+    /// Decode (and later save) the files with this encoding instead of the detected one
+    #[arg(long, value_name = "ENCODING", value_parser = ENCODINGS)]
+    pub encoding: Option<String>,
+
+    /// Syntax highlighting language, instead of the one guessed from the extension
+    #[arg(long, value_name = "LANGUAGE")]
+    pub language: Option<String>,
+
+    /// Override a config value for this session (e.g. --set autoSave=false)
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+
+    /// Open a named session, created if it does not exist yet
+    #[arg(long, value_name = "NAME")]
+    pub session: Option<String>,
+
+    /// Open the files in a new window of the running instance
+    #[arg(long)]
+    pub new_window: bool,
+
+    /// Wait for the files (or the window) to be closed before returning
+    #[arg(long, short = 'w')]
+    pub wait: bool,
+
+    /// Marker file identifying a waiting invocation (set by `--wait`)
+    #[arg(long, hide = true, requires = "wait")]
+    pub wait_token: Option<String>,
+
+    /// File holding the stdin contents captured for `-` (set by `-`)
+    #[arg(long, hide = true)]
+    pub stdin_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Show version information
+    Version,
+    /// Manage named sessions
+    Session {
+        #[command(subcommand)]
+        action: SessionCommands,
+    },
+    /// Read and change settings
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
+    /// Check the environment: directories, config, themes, fonts and display
+    Doctor,
+    /// Convert the encoding and line endings of text files
+    #[command(group(clap::ArgGroup::new("target").args(["to", "eol"]).required(true).multiple(true)))]
+    Convert {
+        /// Target encoding
+        #[arg(long, value_enum, ignore_case = true)]
+        to: Option<EncodingArg>,
+        /// Write a byte order mark (with --to utf-8)
+        #[arg(long, requires = "to")]
+        bom: bool,
+        /// Target line endings
+        #[arg(long, value_enum, ignore_case = true)]
+        eol: Option<LineEnding>,
+        /// Encoding of files without a BOM (default: UTF-8, or Windows-1252 when not valid UTF-8)
+        #[arg(long, value_enum, ignore_case = true)]
+        from: Option<EncodingArg>,
+        /// Report what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Write nothing, and exit with an error if any file needs converting
+        #[arg(long)]
+        check: bool,
+        /// Files to convert
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Manage and validate themes
+    Theme {
+        #[command(subcommand)]
+        action: ThemeCommands,
+    },
+    /// Call a method of the automation API of the running instance
+    Rpc {
+        /// Method name (e.g. tabs.list)
+        method: String,
+        /// Parameters, as a JSON object
+        params: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionCommands {
+    /// List named sessions
+    List,
+    /// Delete a named session and its unsaved buffers
+    Delete {
+        /// Session name
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Print the effective value of a setting, or of all settings
+    Get {
+        /// Setting name (e.g. autoSaveDelay)
+        key: Option<String>,
+    },
+    /// Change a setting in the user config
+    Set {
+        /// Setting name
+        key: String,
+        /// New value, as JSON (false, 500, "text") or plain text
+        value: String,
+    },
+    /// Remove a setting from the user config, so it is inherited again
+    Unset {
+        /// Setting name
+        key: String,
+    },
+    /// Print the path of the user config
+    Path,
+    /// Open the user config in $VISUAL/$EDITOR (or Velt) and check it afterwards
+    Edit,
+    /// Reset the user config to the defaults, keeping a backup
+    Reset {
+        /// Do not ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ThemeCommands {
+    /// List available themes (* marks the startup theme)
+    List,
+    /// Print a theme as JSON
+    Show {
+        /// Theme name
+        name: String,
+    },
+    /// Check theme files, reporting where they are invalid
+    Validate {
+        /// Theme JSON file(s)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Write a theme to a file, or to stdout
+    Export {
+        /// Theme name
+        name: String,
+        /// Output file
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+    /// Validate a theme file and add it to the themes directory
+    Import {
+        /// Theme JSON file
+        file: PathBuf,
+        /// Name to import it as (defaults to the file name)
+        #[arg(long)]
+        name: Option<String>,
+        /// Replace an existing theme with the same name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Apply a theme
+    Set {
+        /// Theme name
+        name: String,
+        /// Also load it on startup
+        #[arg(long)]
+        startup: bool,
+    },
+    /// Delete a custom theme
+    Delete {
+        /// Theme name
+        name: String,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingArg {
+    #[value(name = "utf-8")]
+    Utf8,
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+    #[value(name = "windows-1252")]
+    Windows1252,
+}
+
+impl EncodingArg {
+    /// The editor's label for the encoding, with or without a BOM for UTF-8
+    /// (UTF-16 is always written with one)
+    pub fn label(self, bom: bool) -> &'static str {
+        match self {
+            EncodingArg::Utf8 if bom => "UTF-8-BOM",
+            EncodingArg::Utf8 => "UTF-8",
+            EncodingArg::Utf16Le => "UTF-16LE",
+            EncodingArg::Utf16Be => "UTF-16BE",
+            EncodingArg::Windows1252 => "WINDOWS-1252",
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// Encodings accepted by `--encoding`, as labelled by the editor
+pub const ENCODINGS: [&str; 5] = ["UTF-8", "UTF-8-BOM", "UTF-16LE", "UTF-16BE", "WINDOWS-1252"];
+
+/// A file to open, with the position to put the cursor at (1-based)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CliFile {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+    /// Given as a `velt://` link
+    #[serde(skip)]
+    pub from_link: bool,
+    /// Ask before opening: a link to a file outside the known workspaces
+    #[serde(rename = "confirmOpen", skip_serializing_if = "std::ops::Not::not")]
+    pub confirm_open: bool,
+}
+
+/// How the frontend should open the files of an invocation
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OpenOptions {
+    /// Set when the invocation waits for the opened tabs to be closed
+    #[serde(rename = "waitToken", skip_serializing_if = "Option::is_none")]
+    pub wait_token: Option<String>,
+    pub readonly: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Text piped to `velt -`, opened as an untitled buffer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<StdinBuffer>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StdinBuffer {
+    pub content: String,
+    pub encoding: String,
+}
+
+impl Cli {
+    /// Build the options of this invocation, taking over its captured stdin
+    pub fn open_options(&self) -> OpenOptions {
+        let stdin = self.stdin_file.as_deref().and_then(|path| {
+            match take_stdin(path, self.encoding.as_deref()) {
+                Ok(buffer) => Some(buffer),
+                Err(e) => {
+                    eprintln!("Failed to read piped input: {}", e);
+                    None
+                }
+            }
+        });
+
+        OpenOptions {
+            wait_token: self.wait_token.clone(),
+            readonly: self.readonly,
+            encoding: self.encoding.clone(),
+            language: self.language.clone(),
+            stdin,
+        }
+    }
+
+    /// Whether `-` was given, to open the contents of stdin
+    pub fn reads_stdin(&self) -> bool {
+        self.files.iter().any(|file| file == "-")
+    }
+
+    /// Whether this invocation must first hand over to a second process, see [`relaunch`]
+    pub fn needs_relaunch(&self) -> bool {
+        (self.wait && self.wait_token.is_none())
+            || (self.reads_stdin() && self.stdin_file.is_none())
+    }
+
+    /// Check the `velt://` and `file://` URLs among the file arguments
+    pub fn check_urls(&self) -> Result<(), String> {
+        self.files
+            .iter()
+            .filter(|file| deep_link::is_url(file))
+            .try_for_each(|url| deep_link::parse(url).map(|_| ()))
+    }
+
+    /// Resolve the file arguments against the directory they were given in,
+    /// keeping files and paths that do not exist yet (new files) but not
+    /// directories. `--line`/`--column` apply to files given without a position.
+    /// Invalid URLs are left out, see [`Cli::check_urls`].
+    pub fn resolve_files(&self, cwd: &Path) -> Vec<CliFile> {
+        self.files
+            .iter()
+            .filter(|file| *file != "-")
+            .filter_map(|file| {
+                if !deep_link::is_url(file) {
+                    return Some(parse_file_arg(file, cwd));
+                }
+                deep_link::parse(file)
+                    .map_err(|e| eprintln!("Ignoring {}", e))
+                    .ok()
+            })
+            .filter(|file| {
+                let path = Path::new(&file.path);
+                path.is_file() || !path.exists()
+            })
+            .map(|file| match file.line {
+                Some(_) => file,
+                None => CliFile {
+                    line: self.line,
+                    column: self.column,
+                    ..file
+                },
+            })
+            .collect()
+    }
+}
+
+/// Split a `path:line[:column]` argument, as printed by compilers and grep.
+/// The whole argument is taken as the path when it exists, or when it has no
+/// numeric suffix.
+fn parse_file_arg(arg: &str, cwd: &Path) -> CliFile {
+    let whole = CliFile {
+        path: cwd.join(arg).to_string_lossy().to_string(),
+        ..CliFile::default()
+    };
+    if Path::new(&whole.path).exists() {
+        return whole;
+    }
+
+    let number = |s: &str| s.parse::<u32>().ok().filter(|n| *n > 0);
+    let mut parts = arg.rsplitn(3, ':');
+    let (last, middle, first) = (parts.next(), parts.next(), parts.next());
+    let (path, line, column) = match (first, middle.and_then(number), last.and_then(number)) {
+        (Some(path), Some(line), Some(column)) if !path.is_empty() => (path, line, Some(column)),
+        _ => match arg.rsplit_once(':') {
+            Some((path, line)) if !path.is_empty() => match number(line) {
+                Some(line) => (path, line, None),
+                None => return whole,
+            },
+            _ => return whole,
+        },
+    };
+
+    CliFile {
+        path: cwd.join(path).to_string_lossy().to_string(),
+        line: Some(line),
+        column,
+        ..CliFile::default()
+    }
+}
+
+fn wait_marker_path(token: &str) -> Result<PathBuf, String> {
+    if !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid wait token: '{}'", token));
+    }
+    Ok(config::get_state_dir()?.join("wait").join(token))
+}
+
+fn get_stdin_dir() -> Result<PathBuf, String> {
+    Ok(config::get_state_dir()?.join("stdin"))
+}
+
+/// Run this invocation again with its stdin captured to a file and, for
+/// `--wait`, a wait token, then return the exit code to use.
+///
+/// The second process is needed because an invocation forwarded to the
+/// running instance only passes on its arguments, and exits right away.
+/// This one stays behind: with a token, it watches the token's marker file
+/// until the running instance deletes it.
+pub fn relaunch(cli: &Cli) -> i32 {
+    let token = format!(
+        "{}-{}",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0)
+    );
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+
+    let mut stdin_file = None;
+    if cli.reads_stdin() && cli.stdin_file.is_none() {
+        match capture_stdin(&token) {
+            Ok(path) => {
+                let mut arg = OsString::from("--stdin-file=");
+                arg.push(&path);
+                args.push(arg);
+                stdin_file = Some(path);
+            }
+            Err(e) => {
+                eprintln!("Error: failed to read stdin: {}", e);
+                return 1;
+            }
+        }
+    }
+    let cleanup = |marker: Option<&PathBuf>| {
+        for path in marker.into_iter().chain(stdin_file.as_ref()) {
+            let _ = std::fs::remove_file(path);
+        }
+    };
+
+    let mut marker = None;
+    if cli.wait && cli.wait_token.is_none() {
+        match create_wait_marker(&token) {
+            Ok(path) => {
+                args.push(format!("--wait-token={}", token).into());
+                marker = Some(path);
+            }
+            Err(e) => {
+                cleanup(None);
+                eprintln!("Error: {}", e);
+                return 1;
+            }
+        }
+    }
+
+    let spawned = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(&args)
+            .stdin(std::process::Stdio::null())
+            .spawn()
+    });
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            cleanup(marker.as_ref());
+            eprintln!("Error: failed to start Velt: {}", e);
+            return 1;
+        }
+    };
+
+    // Nothing to wait for: return like the relaunched invocation does
+    let Some(marker) = marker else {
+        return child
+            .wait()
+            .ok()
+            .and_then(|status| status.code())
+            .unwrap_or(1);
+    };
+
+    let mut handed_off = false;
+    loop {
+        if !marker.exists() {
+            return match write_wait_output(&marker) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    1
+                }
+            };
+        }
+        if !handed_off {
+            // A failed start never releases the token
+            if let Ok(Some(status)) = child.try_wait() {
+                if !status.success() {
+                    cleanup(Some(&marker));
+                    return status.code().unwrap_or(1);
+                }
+                handed_off = true;
+            }
+        } else if !session::active_session_in_use() {
+            // The instance holding the files quit or crashed without releasing them
+            cleanup(Some(&marker));
+            eprintln!("Error: Velt exited before the files were closed");
+            return 1;
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Save all of stdin for the instance that opens it
+fn capture_stdin(token: &str) -> Result<PathBuf, String> {
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+
+    let path = get_stdin_dir()?.join(token);
+    fs_util::write_atomic(&path, &bytes)?;
+    Ok(path)
+}
+
+/// Decode captured stdin and delete the capture. Without a BOM or an
+/// `--encoding`, input that is not UTF-8 is read as Windows-1252, as
+/// `velt convert` does: piped text has no file to reopen in another encoding.
+fn take_stdin(path: &Path, encoding: Option<&str>) -> Result<StdinBuffer, String> {
+    // Only ever read captures: the path comes from the command line
+    let dir = std::fs::canonicalize(get_stdin_dir()?).map_err(|e| e.to_string())?;
+    let path = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
+    if path.parent() != Some(dir.as_path()) {
+        return Err(format!("Not captured input: {}", path.display()));
+    }
+
+    let bytes = std::fs::read(&path).map_err(|e| e.to_string());
+    let _ = std::fs::remove_file(&path);
+    let (content, encoding) = crate::convert::decode_text(&bytes?, encoding)?;
+    Ok(StdinBuffer { content, encoding })
+}
+
+fn create_wait_marker(token: &str) -> Result<PathBuf, String> {
+    let marker = wait_marker_path(token)?;
+    if let Some(parent) = marker.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(&marker, b"").map_err(|e| e.to_string())?;
+    Ok(marker)
+}
+
+/// Pass the final text of a piped buffer on to stdout, when it is not a terminal
+fn write_wait_output(marker: &Path) -> Result<(), String> {
+    let output_path = marker.with_extension("out");
+    let Ok(output) = std::fs::read(&output_path) else {
+        return Ok(());
+    };
+    let _ = std::fs::remove_file(&output_path);
+
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        return Ok(());
+    }
+    stdout
+        .write_all(&output)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| format!("Failed to write output: {}", e))
+}
+
+/// Let a waiting invocation return, with the text to write to its stdout
+/// when it opened piped input
+pub fn release_wait(token: &str, output: Option<&str>) -> Result<(), String> {
+    let marker = wait_marker_path(token)?;
+    if let Some(output) = output {
+        fs_util::write_atomic(&marker.with_extension("out"), output.as_bytes())?;
+    }
+    if marker.exists() {
+        std::fs::remove_file(&marker).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
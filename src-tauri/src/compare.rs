@@ -0,0 +1,302 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompareOptions {
+    /// Glob patterns matched against relative paths and entry names
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default, rename = "followSymlinks")]
+    pub follow_symlinks: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStatus {
+    #[serde(rename = "identical")]
+    Identical,
+    #[serde(rename = "different")]
+    Different,
+    #[serde(rename = "leftOnly")]
+    LeftOnly,
+    #[serde(rename = "rightOnly")]
+    RightOnly,
+    /// The entry could not be read on one side or both, see `error`
+    #[serde(rename = "error")]
+    Error,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ComparedEntry {
+    /// Path relative to the compared roots, always `/`-separated
+    pub path: String,
+    #[serde(rename = "isDir")]
+    pub is_dir: bool,
+    pub status: EntryStatus,
+    #[serde(rename = "leftPath")]
+    pub left_path: Option<String>,
+    #[serde(rename = "rightPath")]
+    pub right_path: Option<String>,
+    #[serde(rename = "leftSize")]
+    pub left_size: Option<u64>,
+    #[serde(rename = "rightSize")]
+    pub right_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirectoryComparison {
+    pub left: String,
+    pub right: String,
+    pub entries: Vec<ComparedEntry>,
+}
+
+/// A run of lines that is equal, or changed, between two texts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiffChunk {
+    /// One of `equal`, `insert`, `delete` or `replace`
+    pub tag: String,
+    #[serde(rename = "leftStart")]
+    pub left_start: usize,
+    #[serde(rename = "leftLines")]
+    pub left_lines: usize,
+    #[serde(rename = "rightStart")]
+    pub right_start: usize,
+    #[serde(rename = "rightLines")]
+    pub right_lines: usize,
+}
+
+struct WalkedEntry {
+    path: PathBuf,
+    is_dir: bool,
+    size: u64,
+    /// Where a symlink points, when symlinks are not followed
+    link_target: Option<PathBuf>,
+    error: Option<String>,
+}
+
+/// Recursively compare two directory trees, classifying every entry by content hash.
+///
+/// Entries are returned sorted by relative path. A directory is `identical` only when
+/// everything below it is. Entries that cannot be read get the `error` status
+/// rather than failing the whole comparison.
+pub fn compare_directories(
+    left: &Path,
+    right: &Path,
+    options: &CompareOptions,
+) -> Result<DirectoryComparison, String> {
+    for root in [left, right] {
+        if !root.is_dir() {
+            return Err(format!("'{}' is not a directory", root.display()));
+        }
+    }
+
+    let ignore = build_ignore_set(&options.ignore)?;
+    let left_entries = walk(left, &ignore, options.follow_symlinks);
+    let right_entries = walk(right, &ignore, options.follow_symlinks);
+
+    let paths: BTreeSet<&String> = left_entries.keys().chain(right_entries.keys()).collect();
+    let mut entries = Vec::with_capacity(paths.len());
+
+    for rel in paths {
+        let l = left_entries.get(rel);
+        let r = right_entries.get(rel);
+
+        let mut error = l
+            .and_then(|e| e.error.clone())
+            .or_else(|| r.and_then(|e| e.error.clone()));
+        let status = match (l, r) {
+            _ if error.is_some() => EntryStatus::Error,
+            (Some(_), None) => EntryStatus::LeftOnly,
+            (None, Some(_)) => EntryStatus::RightOnly,
+            (Some(l), Some(r)) if l.is_dir && r.is_dir => EntryStatus::Identical,
+            (Some(l), Some(r)) if l.link_target.is_some() || r.link_target.is_some() => {
+                if l.link_target == r.link_target {
+                    EntryStatus::Identical
+                } else {
+                    EntryStatus::Different
+                }
+            }
+            (Some(l), Some(r)) if l.is_dir != r.is_dir || l.size != r.size => {
+                EntryStatus::Different
+            }
+            (Some(l), Some(r)) => match (hash_file(&l.path), hash_file(&r.path)) {
+                (Ok(l), Ok(r)) if l == r => EntryStatus::Identical,
+                (Ok(_), Ok(_)) => EntryStatus::Different,
+                (Err(e), _) | (_, Err(e)) => {
+                    error = Some(e);
+                    EntryStatus::Error
+                }
+            },
+            (None, None) => unreachable!(),
+        };
+
+        entries.push(ComparedEntry {
+            path: rel.clone(),
+            is_dir: l.or(r).is_some_and(|e| e.is_dir),
+            status,
+            left_path: l.map(|e| e.path.to_string_lossy().to_string()),
+            right_path: r.map(|e| e.path.to_string_lossy().to_string()),
+            left_size: l.filter(|e| !e.is_dir).map(|e| e.size),
+            right_size: r.filter(|e| !e.is_dir).map(|e| e.size),
+            error,
+        });
+    }
+
+    propagate_directory_status(&mut entries);
+
+    Ok(DirectoryComparison {
+        left: left.to_string_lossy().to_string(),
+        right: right.to_string_lossy().to_string(),
+        entries,
+    })
+}
+
+/// Line-based diff of two texts, for the side-by-side file diff view
+pub fn diff_texts(left: &str, right: &str) -> Vec<DiffChunk> {
+    let left_lines: Vec<&str> = left.split_inclusive('\n').collect();
+    let right_lines: Vec<&str> = right.split_inclusive('\n').collect();
+
+    capture_diff_slices(Algorithm::Myers, &left_lines, &right_lines)
+        .iter()
+        .map(|op| {
+            let (tag, left_range, right_range) = op.as_tag_tuple();
+            let tag = match tag {
+                DiffTag::Equal => "equal",
+                DiffTag::Insert => "insert",
+                DiffTag::Delete => "delete",
+                DiffTag::Replace => "replace",
+            };
+            DiffChunk {
+                tag: tag.to_string(),
+                left_start: left_range.start,
+                left_lines: left_range.len(),
+                right_start: right_range.start,
+                right_lines: right_range.len(),
+            }
+        })
+        .collect()
+}
+
+fn build_ignore_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| format!("Invalid ignore pattern '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Collect every entry below `root`, keyed by its `/`-separated relative path.
+/// Entries that cannot be read are kept with their error.
+fn walk(root: &Path, ignore: &GlobSet, follow_symlinks: bool) -> BTreeMap<String, WalkedEntry> {
+    let mut entries = BTreeMap::new();
+
+    let walker = WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(follow_symlinks)
+        .into_iter()
+        .filter_entry(|entry| {
+            let rel = relative_path(root, entry.path());
+            !(ignore.is_match(&rel) || ignore.is_match(entry.file_name()))
+        });
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                // A folder that cannot be listed, a dangling link, a link loop...
+                let Some(path) = e.path() else { continue };
+                let error = Some(e.to_string());
+                entries
+                    .entry(relative_path(root, path))
+                    .and_modify(|entry: &mut WalkedEntry| entry.error = error.clone())
+                    .or_insert_with(|| WalkedEntry {
+                        path: path.to_path_buf(),
+                        is_dir: false,
+                        size: 0,
+                        link_target: None,
+                        error,
+                    });
+                continue;
+            }
+        };
+
+        let walked = match entry.metadata() {
+            Ok(metadata) => WalkedEntry {
+                path: entry.path().to_path_buf(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                link_target: if entry.path_is_symlink() && !follow_symlinks {
+                    fs::read_link(entry.path()).ok()
+                } else {
+                    None
+                },
+                error: None,
+            },
+            Err(e) => WalkedEntry {
+                path: entry.path().to_path_buf(),
+                is_dir: false,
+                size: 0,
+                link_target: None,
+                error: Some(e.to_string()),
+            },
+        };
+        entries.insert(relative_path(root, entry.path()), walked);
+    }
+
+    entries
+}
+
+fn relative_path(root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn hash_file(path: &Path) -> Result<Vec<u8>, String> {
+    let mut file =
+        fs::File::open(path).map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Mark directories present on both sides as `different` if any descendant
+/// is not identical
+fn propagate_directory_status(entries: &mut [ComparedEntry]) {
+    // Every ancestor of a changed entry, each added once
+    let mut changed_dirs: HashSet<String> = HashSet::new();
+    for entry in entries
+        .iter()
+        .filter(|e| e.status != EntryStatus::Identical)
+    {
+        let mut path = entry.path.as_str();
+        while let Some((parent, _)) = path.rsplit_once('/') {
+            if !changed_dirs.insert(parent.to_string()) {
+                break;
+            }
+            path = parent;
+        }
+    }
+
+    for entry in entries.iter_mut() {
+        if entry.is_dir
+            && entry.status == EntryStatus::Identical
+            && changed_dirs.contains(&entry.path)
+        {
+            entry.status = EntryStatus::Different;
+        }
+    }
+}
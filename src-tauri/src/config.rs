@@ -1,33 +1,175 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+/// Version of the config format, stored in the user config as `version`.
+/// Raise it together with a new entry in [`MIGRATIONS`].
+pub const CONFIG_VERSION: u32 = 1;
+
+const VERSION_KEY: &str = "version";
+
+// Missing fields take their default, so configs of older versions still load
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppConfig {
+    /// Config format version; always the current one once loaded
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default = "default_theme")]
     pub theme: String,
-    #[serde(rename = "themesDir")]
+    #[serde(rename = "themesDir", default)]
     pub themes_dir: Option<String>,
-    #[serde(rename = "autoSave")]
+    #[serde(rename = "autoSave", default = "default_true")]
     pub auto_save: bool,
-    #[serde(rename = "autoSaveDelay")]
+    #[serde(rename = "autoSaveDelay", default = "default_auto_save_delay")]
     pub auto_save_delay: u32,
-    #[serde(rename = "recentFiles")]
+    #[serde(rename = "recentFiles", default)]
     pub recent_files: Vec<String>,
+    /// Reopen the tabs and windows of the previous session on startup
+    #[serde(rename = "restoreSession", default = "default_true")]
+    pub restore_session: bool,
+    #[serde(rename = "showMenuIcons", default = "default_true")]
+    pub show_menu_icons: bool,
+    #[serde(rename = "fontSize", default = "default_font_size")]
+    pub font_size: u32,
+    /// CSS font family of the editor; the platform's monospace font when unset
+    #[serde(rename = "fontFamily", default)]
+    pub font_family: Option<String>,
+    #[serde(rename = "tabSize", default = "default_tab_size")]
+    pub tab_size: u32,
+    #[serde(rename = "wordWrap", default)]
+    pub word_wrap: bool,
+    #[serde(rename = "showInvisibles", default)]
+    pub show_invisibles: bool,
+    #[serde(rename = "autoIndent", default = "default_true")]
+    pub auto_indent: bool,
+    /// Editor zoom in percent
+    #[serde(rename = "zoomLevel", default = "default_zoom_level")]
+    pub zoom_level: u32,
+}
+
+fn default_version() -> u32 {
+    CONFIG_VERSION
+}
+
+fn default_theme() -> String {
+    "default-dark".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_auto_save_delay() -> u32 {
+    1000
+}
+
+fn default_font_size() -> u32 {
+    14
+}
+
+fn default_tab_size() -> u32 {
+    2
+}
+
+fn default_zoom_level() -> u32 {
+    100
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
-            theme: "default-dark".to_string(),
+            version: default_version(),
+            theme: default_theme(),
             themes_dir: None,
             auto_save: true,
-            auto_save_delay: 1000,
+            auto_save_delay: default_auto_save_delay(),
             recent_files: Vec::new(),
+            restore_session: true,
+            show_menu_icons: true,
+            font_size: default_font_size(),
+            font_family: None,
+            tab_size: default_tab_size(),
+            word_wrap: false,
+            show_invisibles: false,
+            auto_indent: true,
+            zoom_level: default_zoom_level(),
         }
     }
 }
 
+impl AppConfig {
+    /// Check the values their types alone do not constrain, of some settings
+    /// only (e.g. the ones being changed), so that an invalid value elsewhere
+    /// does not get in the way of fixing it
+    pub fn validate_only(&self, keys: &Map<String, Value>) -> Result<(), String> {
+        match self
+            .invalid_settings()
+            .into_iter()
+            .find(|(key, _)| keys.contains_key(*key))
+        {
+            Some((_, error)) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Settings with values out of their range, with the reason
+    fn invalid_settings(&self) -> Vec<(&'static str, String)> {
+        let mut invalid = Vec::new();
+        let mut in_range = |key: &'static str, value: u32, min: u32, max: u32| {
+            if !(min..=max).contains(&value) {
+                invalid.push((
+                    key,
+                    format!("{} must be between {} and {}, not {}", key, min, max, value),
+                ));
+            }
+        };
+        in_range("autoSaveDelay", self.auto_save_delay, 100, 3_600_000);
+        in_range("fontSize", self.font_size, 6, 72);
+        in_range("tabSize", self.tab_size, 1, 16);
+        in_range("zoomLevel", self.zoom_level, 50, 200);
+        if self
+            .font_family
+            .as_ref()
+            .is_some_and(|family| family.trim().is_empty())
+        {
+            invalid.push((
+                "fontFamily",
+                "fontFamily must not be empty (null for the default font)".to_string(),
+            ));
+        }
+        invalid
+    }
+
+    /// Put invalid settings back to their defaults, with a warning for each,
+    /// so that one bad value does not cost the rest of the config
+    fn reset_invalid(self) -> Result<Self, String> {
+        let invalid = self.invalid_settings();
+        if invalid.is_empty() {
+            return Ok(self);
+        }
+
+        let defaults = match serde_json::to_value(Self::default()) {
+            Ok(Value::Object(map)) => map,
+            _ => return Err("Failed to serialize default config".to_string()),
+        };
+        let mut values = match serde_json::to_value(self).map_err(|e| e.to_string())? {
+            Value::Object(map) => map,
+            _ => return Err("Failed to serialize config".to_string()),
+        };
+        for (key, error) in invalid {
+            eprintln!("Ignoring invalid setting ({}), using the default", error);
+            if let Some(default) = defaults.get(key) {
+                values.insert(key.to_string(), default.clone());
+            }
+        }
+        serde_json::from_value(Value::Object(values)).map_err(|e| e.to_string())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EditorTheme {
     pub background: String,
@@ -408,6 +550,22 @@ pub fn get_config_dir() -> Result<PathBuf, String> {
     Ok(config_dir)
 }
 
+/// Get the Velt state directory path (caches, indexes and other non-config data)
+/// Supports VELT_STATE_HOME environment variable for custom location
+/// Defaults to the platform state directory, or the local data directory where there is none
+pub fn get_state_dir() -> Result<PathBuf, String> {
+    if let Ok(custom_dir) = env::var("VELT_STATE_HOME") {
+        return Ok(PathBuf::from(custom_dir));
+    }
+
+    let state_dir = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .ok_or("Could not determine state directory")?
+        .join("velt");
+
+    Ok(state_dir)
+}
+
 /// Get the themes directory path (from config or default)
 pub fn get_themes_dir(config: &AppConfig) -> Result<PathBuf, String> {
     if let Some(custom_dir) = &config.themes_dir {
@@ -426,13 +584,20 @@ pub fn initialize_config() -> Result<(), String> {
     fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
     fs::create_dir_all(&themes_dir).map_err(|e| e.to_string())?;
 
-    // Create default config.json if it doesn't exist
+    // Create an empty user config.json if it doesn't exist. Values left out
+    // are inherited from the defaults and the system-wide config.
     let config_path = config_dir.join("config.json");
     if !config_path.exists() {
-        let default_config = AppConfig::default();
-        let config_json =
-            serde_json::to_string_pretty(&default_config).map_err(|e| e.to_string())?;
-        fs::write(&config_path, config_json).map_err(|e| e.to_string())?;
+        write_user_layer(&config_path, Map::new())?;
+    }
+    match migrate_user_config() {
+        Ok(Some(backup)) => eprintln!(
+            "Upgraded config to version {}, previous one kept as {}",
+            CONFIG_VERSION,
+            backup.display()
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to upgrade config: {}", e),
     }
 
     // Create default themes if they don't exist
@@ -886,29 +1051,690 @@ fn create_default_themes(themes_dir: &Path) -> Result<(), String> {
     Ok(())
 }
 
-/// Load config from file
-pub fn load_config() -> Result<AppConfig, String> {
-    let config_path = get_config_dir()?.join("config.json");
+/// Configuration layers, from lowest to highest precedence
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigLayer {
+    #[serde(rename = "default")]
+    Default,
+    #[serde(rename = "system")]
+    System,
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "workspace")]
+    Workspace,
+    #[serde(rename = "cli")]
+    Cli,
+    /// Values locked by an administrator; they override every other layer
+    #[serde(rename = "policy")]
+    Policy,
+}
 
-    if !config_path.exists() {
-        return Ok(AppConfig::default());
+/// An effective config value and the layer it came from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConfigValueSource {
+    pub key: String,
+    pub value: Value,
+    pub layer: ConfigLayer,
+    /// File the value was read from (none for defaults and CLI overrides)
+    pub path: Option<String>,
+    /// Whether the value is locked by the system policy and cannot be changed
+    pub locked: bool,
+}
+
+/// A user config that could not be read, moved aside for the defaults, or
+/// with settings of the wrong type dropped
+#[derive(Debug, Serialize, Clone)]
+pub struct ConfigRecovery {
+    /// Where the unreadable file was moved (or copied) to
+    #[serde(rename = "backupPath")]
+    pub backup_path: String,
+    pub error: String,
+    /// Position of the JSON syntax error (1-based), if that was the problem
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Settings dropped for having the wrong type; empty when the whole file was replaced
+    #[serde(rename = "droppedSettings")]
+    pub dropped_settings: Vec<String>,
+}
+
+impl ConfigRecovery {
+    /// What was done about the broken config, for the log
+    pub fn describe(&self) -> String {
+        if self.dropped_settings.is_empty() {
+            format!(
+                "Config could not be read ({}), moved to {}",
+                self.error, self.backup_path
+            )
+        } else {
+            format!(
+                "Dropped config settings of the wrong type ({}), previous config kept as {}",
+                self.error, self.backup_path
+            )
+        }
     }
+}
 
-    let config_content = fs::read_to_string(&config_path).map_err(|e| e.to_string())?;
-    let config: AppConfig = serde_json::from_str(&config_content).map_err(|e| e.to_string())?;
+/// System policy file contents
+#[derive(Debug, Deserialize, Default)]
+struct Policy {
+    /// Locked settings and the values they are pinned to
+    #[serde(default)]
+    locked: Map<String, Value>,
+}
 
-    Ok(config)
+/// One config layer: where it was read from and the values it sets
+struct LayerValues {
+    layer: ConfigLayer,
+    path: Option<PathBuf>,
+    values: Map<String, Value>,
 }
 
-/// Save config to file
-pub fn save_config(config: &AppConfig) -> Result<(), String> {
-    let config_path = get_config_dir()?.join("config.json");
-    let config_json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
-    fs::write(&config_path, config_json).map_err(|e| e.to_string())?;
+/// All layers merged, with the layer each top-level key was taken from
+struct ResolvedConfig {
+    value: Map<String, Value>,
+    sources: HashMap<String, ConfigLayer>,
+    layers: Vec<LayerValues>,
+}
 
+// Root of the open workspace, whose `.velt/settings.json` forms the workspace layer
+static WORKSPACE_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// Values given on the command line with `--set key=value`
+static CLI_OVERRIDES: Mutex<Option<Map<String, Value>>> = Mutex::new(None);
+
+/// Get the system-wide config file path
+/// Supports VELT_SYSTEM_CONFIG environment variable for custom location
+pub fn get_system_config_path() -> PathBuf {
+    if let Ok(custom_path) = env::var("VELT_SYSTEM_CONFIG") {
+        return PathBuf::from(custom_path);
+    }
+
+    #[cfg(target_os = "windows")]
+    return PathBuf::from(env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".into()))
+        .join("Velt")
+        .join("config.json");
+
+    #[cfg(target_os = "macos")]
+    return PathBuf::from("/Library/Application Support/Velt/config.json");
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    return PathBuf::from("/etc/velt/config.json");
+}
+
+/// Get the system policy file path (next to the system-wide config)
+/// Supports VELT_POLICY environment variable for custom location
+pub fn get_policy_path() -> PathBuf {
+    if let Ok(custom_path) = env::var("VELT_POLICY") {
+        return PathBuf::from(custom_path);
+    }
+    get_system_config_path().with_file_name("policy.json")
+}
+
+/// Load the settings locked by the system policy, with their enforced values
+pub fn load_locked_settings() -> Result<Map<String, Value>, String> {
+    let path = get_policy_path();
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let policy: Policy =
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(policy.locked)
+}
+
+/// Get the user config file path
+pub fn get_user_config_path() -> Result<PathBuf, String> {
+    Ok(get_config_dir()?.join("config.json"))
+}
+
+/// Get the workspace settings path of the current workspace, if one is set
+pub fn get_workspace_config_path() -> Option<PathBuf> {
+    let root = WORKSPACE_ROOT.lock().ok()?.clone()?;
+    Some(root.join(".velt").join("settings.json"))
+}
+
+/// Set (or clear) the workspace whose `.velt/settings.json` is layered over the user config
+pub fn set_workspace_root(root: Option<PathBuf>) {
+    if let Ok(mut workspace) = WORKSPACE_ROOT.lock() {
+        *workspace = root;
+    }
+}
+
+pub fn get_workspace_root() -> Option<PathBuf> {
+    WORKSPACE_ROOT.lock().ok()?.clone()
+}
+
+/// Find the nearest directory at or above `start` that contains a `.velt` directory
+pub fn find_workspace_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(".velt").is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// Parse a config value given on the command line: as JSON when possible
+/// (`false`, `500`), otherwise taken as a string
+pub fn parse_config_value(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+/// Set the command line overrides from `key=value` pairs. Values are parsed as
+/// JSON when possible (`autoSave=false`, `autoSaveDelay=500`), otherwise taken as strings.
+pub fn set_cli_overrides(pairs: &[String]) -> Result<(), String> {
+    let mut overrides = Map::new();
+    for pair in pairs {
+        let (key, raw) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid override '{}', expected key=value", pair))?;
+        overrides.insert(key.trim().to_string(), parse_config_value(raw));
+    }
+
+    *CLI_OVERRIDES.lock().map_err(|e| e.to_string())? = Some(overrides);
     Ok(())
 }
 
+/// Read one config layer file; a missing file is an empty layer
+fn read_layer(path: &Path) -> Result<Map<String, Value>, String> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match serde_json::from_str(&content) {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(_) => Err(format!("{}: expected a JSON object", path.display())),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Write the user config layer, stamped with the current version, or with the
+/// version of the file it replaces if a newer Velt wrote that one: its values
+/// are already migrated and must not be migrated again. Written atomically:
+/// a crash mid-write must not leave a truncated config behind.
+fn write_user_layer(path: &Path, mut user: Map<String, Value>) -> Result<(), String> {
+    let existing = read_layer(path).map_or(0, |layer| layer_version(&layer));
+    user.insert(VERSION_KEY.to_string(), json!(existing.max(CONFIG_VERSION)));
+    let json = serde_json::to_string_pretty(&user).map_err(|e| e.to_string())?;
+    crate::fs_util::write_atomic(path, json.as_bytes())
+}
+
+/// Move an unreadable user config (invalid JSON, or not an object) aside as
+/// `config.corrupt-<timestamp>.json` and start over from the defaults, so
+/// that a broken file cannot keep the app from starting. Settings of the
+/// wrong type are dropped from it instead, keeping a copy of the file the same way.
+pub fn recover_user_config() -> Result<Option<ConfigRecovery>, String> {
+    let path = get_user_config_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let (error, position) = match serde_json::from_str::<Value>(&content) {
+        Ok(Value::Object(user)) => return drop_mistyped_settings(&path, user),
+        Ok(_) => ("expected a JSON object".to_string(), None),
+        Err(e) => (e.to_string(), Some((e.line(), e.column()))),
+    };
+
+    let backup = corrupt_backup_path(&path);
+    fs::rename(&path, &backup).map_err(|e| e.to_string())?;
+    write_user_layer(&path, Map::new())?;
+
+    Ok(Some(ConfigRecovery {
+        backup_path: backup.to_string_lossy().to_string(),
+        error,
+        line: position.map(|(line, _)| line),
+        column: position.map(|(_, column)| column),
+        dropped_settings: Vec::new(),
+    }))
+}
+
+/// Drop the settings of a user config that do not have the type of the
+/// setting (e.g. `"autoSave": "yes"`), which would keep the config from loading
+fn drop_mistyped_settings(
+    path: &Path,
+    mut user: Map<String, Value>,
+) -> Result<Option<ConfigRecovery>, String> {
+    let defaults = match serde_json::to_value(AppConfig::default()) {
+        Ok(Value::Object(map)) => map,
+        _ => return Err("Failed to serialize default config".to_string()),
+    };
+
+    let mut migrated = user.clone();
+    migrate_layer(&mut migrated);
+    let mut errors = Vec::new();
+    for (key, value) in migrated {
+        let mut values = defaults.clone();
+        values.insert(key.clone(), value);
+        if let Err(e) = serde_json::from_value::<AppConfig>(Value::Object(values)) {
+            errors.push((key, e.to_string()));
+        }
+    }
+    if errors.is_empty() {
+        return Ok(None);
+    }
+
+    let backup = corrupt_backup_path(path);
+    fs::copy(path, &backup).map_err(|e| e.to_string())?;
+    for (key, _) in &errors {
+        user.remove(key);
+    }
+    write_user_layer(path, user)?;
+
+    Ok(Some(ConfigRecovery {
+        backup_path: backup.to_string_lossy().to_string(),
+        error: errors
+            .iter()
+            .map(|(key, e)| format!("{}: {}", key, e))
+            .collect::<Vec<_>>()
+            .join("; "),
+        line: None,
+        column: None,
+        dropped_settings: errors.into_iter().map(|(key, _)| key).collect(),
+    }))
+}
+
+/// A free `config.corrupt-<timestamp>.json` name next to the user config
+fn corrupt_backup_path(path: &Path) -> PathBuf {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut backup = path.with_file_name(format!("config.corrupt-{}.json", timestamp));
+    let mut n = 1;
+    while backup.exists() {
+        n += 1;
+        backup = path.with_file_name(format!("config.corrupt-{}-{}.json", timestamp, n));
+    }
+    backup
+}
+
+/// Upgrades of the user config, in order: entry `n` turns a version `n`
+/// config into a version `n + 1` one
+const MIGRATIONS: [fn(&mut Map<String, Value>); CONFIG_VERSION as usize] = [strip_legacy_defaults];
+
+/// 0 → 1: configs written before settings were layered hold every setting,
+/// shadowing the system-wide config. Drop those at their defaults of the time.
+fn strip_legacy_defaults(user: &mut Map<String, Value>) {
+    let legacy_defaults = [
+        ("theme", json!("default-dark")),
+        ("themesDir", Value::Null),
+        ("autoSave", json!(true)),
+        ("autoSaveDelay", json!(1000)),
+        ("recentFiles", json!([])),
+    ];
+    for (key, value) in legacy_defaults {
+        if user.get(key) == Some(&value) {
+            user.remove(key);
+        }
+    }
+}
+
+/// Version a user config was written with; configs without one predate versioning
+fn layer_version(user: &Map<String, Value>) -> u32 {
+    user.get(VERSION_KEY)
+        .and_then(Value::as_u64)
+        .map_or(0, |version| version as u32)
+}
+
+/// Bring the values of a user config up to date, dropping its version.
+/// Configs of a newer Velt are taken as they are.
+fn migrate_layer(user: &mut Map<String, Value>) {
+    let version = layer_version(user) as usize;
+    for migration in MIGRATIONS.iter().skip(version) {
+        migration(user);
+    }
+    user.remove(VERSION_KEY);
+}
+
+/// Upgrade the user config file to the current version, keeping the previous
+/// file next to it as `config.json.v<version>.bak`. Returns the backup path
+/// if the file was upgraded.
+pub fn migrate_user_config() -> Result<Option<PathBuf>, String> {
+    let path = get_user_config_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut user = read_layer(&path)?;
+    let version = layer_version(&user);
+    if version >= CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let backup = path.with_extension(format!("json.v{}.bak", version));
+    fs::copy(&path, &backup).map_err(|e| e.to_string())?;
+    migrate_layer(&mut user);
+    write_user_layer(&path, user)?;
+    Ok(Some(backup))
+}
+
+/// Merge `overlay` into `base`, recursing into objects present in both
+fn merge_values(base: &mut Map<String, Value>, overlay: &Map<String, Value>) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(Value::Object(base_obj)), Value::Object(overlay_obj)) => {
+                merge_values(base_obj, overlay_obj)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn resolve_config() -> Result<ResolvedConfig, String> {
+    let defaults = match serde_json::to_value(AppConfig::default()) {
+        Ok(Value::Object(map)) => map,
+        _ => return Err("Failed to serialize default config".to_string()),
+    };
+
+    let system_path = get_system_config_path();
+    let user_path = get_user_config_path()?;
+
+    // Only the user config is written by Velt and versioned; the others
+    // cannot change the version either
+    let mut system = read_layer(&system_path)?;
+    system.remove(VERSION_KEY);
+    let mut user = read_layer(&user_path)?;
+    migrate_layer(&mut user);
+
+    let mut layers = vec![
+        LayerValues {
+            layer: ConfigLayer::Default,
+            path: None,
+            values: defaults,
+        },
+        LayerValues {
+            layer: ConfigLayer::System,
+            values: system,
+            path: Some(system_path),
+        },
+        LayerValues {
+            layer: ConfigLayer::User,
+            values: user,
+            path: Some(user_path),
+        },
+    ];
+
+    if let Some(workspace_path) = get_workspace_config_path() {
+        let mut workspace = read_layer(&workspace_path)?;
+        workspace.remove(VERSION_KEY);
+        layers.push(LayerValues {
+            layer: ConfigLayer::Workspace,
+            values: workspace,
+            path: Some(workspace_path),
+        });
+    }
+
+    if let Some(cli) = CLI_OVERRIDES.lock().map_err(|e| e.to_string())?.clone() {
+        layers.push(LayerValues {
+            layer: ConfigLayer::Cli,
+            path: None,
+            values: cli,
+        });
+    }
+
+    let locked = load_locked_settings()?;
+    if !locked.is_empty() {
+        layers.push(LayerValues {
+            layer: ConfigLayer::Policy,
+            path: Some(get_policy_path()),
+            values: locked,
+        });
+    }
+
+    let mut value = Map::new();
+    let mut sources = HashMap::new();
+    for layer in &layers {
+        merge_values(&mut value, &layer.values);
+        for key in layer.values.keys() {
+            sources.insert(key.clone(), layer.layer);
+        }
+    }
+
+    Ok(ResolvedConfig {
+        value,
+        sources,
+        layers,
+    })
+}
+
+/// Load the effective config: defaults, then the system-wide config, the user
+/// config, the workspace settings and finally command line overrides. Values
+/// out of range are replaced by their defaults.
+pub fn load_config() -> Result<AppConfig, String> {
+    parse_config()?.reset_invalid()
+}
+
+/// Problems with the values of the effective config, which `load_config` works around
+pub fn config_problems() -> Result<Vec<String>, String> {
+    Ok(parse_config()?
+        .invalid_settings()
+        .into_iter()
+        .map(|(_, error)| error)
+        .collect())
+}
+
+/// The effective config, before its values are validated
+fn parse_config() -> Result<AppConfig, String> {
+    let resolved = resolve_config()?;
+    serde_json::from_value(Value::Object(resolved.value)).map_err(|e| e.to_string())
+}
+
+/// Report every effective config value together with the layer it came from
+pub fn config_sources() -> Result<Vec<ConfigValueSource>, String> {
+    let resolved = resolve_config()?;
+
+    let mut sources = Vec::new();
+    for (key, value) in resolved.value {
+        let layer = resolved
+            .sources
+            .get(&key)
+            .copied()
+            .unwrap_or(ConfigLayer::Default);
+        let path = resolved
+            .layers
+            .iter()
+            .find(|l| l.layer == layer)
+            .and_then(|l| l.path.as_ref())
+            .map(|p| p.to_string_lossy().to_string());
+
+        sources.push(ConfigValueSource {
+            key,
+            value,
+            layer,
+            path,
+            locked: layer == ConfigLayer::Policy,
+        });
+    }
+
+    Ok(sources)
+}
+
+/// Save config to the user layer.
+///
+/// Only values that differ from what the user would otherwise inherit are
+/// written, so the user file doesn't shadow later changes to the defaults or
+/// the system-wide config. Values currently coming from the workspace or the
+/// command line are left out unless they were changed.
+///
+/// Fails without writing anything if a setting locked by the system policy
+/// would be changed, or if a changed setting is invalid.
+pub fn save_config(config: &AppConfig) -> Result<(), String> {
+    let resolved = resolve_config()?;
+    let mut new_values = match serde_json::to_value(config).map_err(|e| e.to_string())? {
+        Value::Object(map) => map,
+        _ => return Err("Failed to serialize config".to_string()),
+    };
+    // Written by write_user_layer, whatever the caller passed
+    new_values.remove(VERSION_KEY);
+
+    let changed: Map<String, Value> = new_values
+        .iter()
+        .filter(|(key, value)| resolved.value.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    config.validate_only(&changed)?;
+
+    write_changes(&resolved, new_values)
+}
+
+/// Change some settings on top of the config as it is on disk now, and write
+/// only those to the user layer, so that changes made elsewhere since the
+/// config was loaded are kept. `change` gets the current config and returns
+/// the settings to change. Fails without writing anything if the config does
+/// not load. Returns the new effective config.
+pub fn update_config(
+    change: impl FnOnce(&AppConfig) -> Result<Map<String, Value>, String>,
+) -> Result<AppConfig, String> {
+    let current = load_config()?;
+    let changes = change(&current)?;
+    write_settings(&current, &changes)?;
+    load_config()
+}
+
+/// Apply `changes` to `current` and write only those settings to the user
+/// layer. Only the changed settings are validated.
+fn write_settings(current: &AppConfig, changes: &Map<String, Value>) -> Result<(), String> {
+    let updated = with_changes(current, changes)?;
+    updated.validate_only(changes)?;
+
+    let mut new_values = match serde_json::to_value(&updated).map_err(|e| e.to_string())? {
+        Value::Object(map) => map,
+        _ => return Err("Failed to serialize config".to_string()),
+    };
+    new_values.retain(|key, _| changes.contains_key(key));
+
+    write_changes(&resolve_config()?, new_values)
+}
+
+/// Write settings to the user layer, see `save_config`
+fn write_changes(resolved: &ResolvedConfig, new_values: Map<String, Value>) -> Result<(), String> {
+    for (key, value) in &new_values {
+        if resolved.sources.get(key) == Some(&ConfigLayer::Policy)
+            && resolved.value.get(key) != Some(value)
+        {
+            return Err(format!("Setting '{}' is locked by your administrator", key));
+        }
+    }
+
+    // What the user layer inherits: defaults and system-wide config
+    let mut inherited = Map::new();
+    let mut user = Map::new();
+    for layer in &resolved.layers {
+        match layer.layer {
+            ConfigLayer::Default | ConfigLayer::System => {
+                merge_values(&mut inherited, &layer.values)
+            }
+            ConfigLayer::User => user = layer.values.clone(),
+            ConfigLayer::Workspace | ConfigLayer::Cli | ConfigLayer::Policy => {}
+        }
+    }
+
+    for (key, value) in new_values {
+        let from_upper_layer = matches!(
+            resolved.sources.get(&key),
+            Some(ConfigLayer::Workspace | ConfigLayer::Cli | ConfigLayer::Policy)
+        );
+
+        if from_upper_layer && resolved.value.get(&key) == Some(&value) {
+            continue;
+        }
+        if inherited.get(&key) == Some(&value) {
+            user.remove(&key);
+        } else {
+            user.insert(key, value);
+        }
+    }
+
+    write_user_layer(&get_user_config_path()?, user)
+}
+
+/// Not validated, so that `velt config` can still fix an out-of-range value
+fn effective_values() -> Result<Map<String, Value>, String> {
+    match serde_json::to_value(parse_config()?).map_err(|e| e.to_string())? {
+        Value::Object(map) => Ok(map),
+        _ => Err("Failed to serialize config".to_string()),
+    }
+}
+
+/// Get the value of one setting as the editor uses it
+pub fn get_config_value(key: &str) -> Result<Value, String> {
+    match serde_json::to_value(load_config()?).map_err(|e| e.to_string())? {
+        Value::Object(mut map) => map
+            .remove(key)
+            .ok_or_else(|| format!("Unknown setting '{}'", key)),
+        _ => Err("Failed to serialize config".to_string()),
+    }
+}
+
+/// A copy of `config` with some settings changed. The values must have the
+/// settings' types.
+pub fn with_changes(config: &AppConfig, changes: &Map<String, Value>) -> Result<AppConfig, String> {
+    let mut values = match serde_json::to_value(config).map_err(|e| e.to_string())? {
+        Value::Object(map) => map,
+        _ => return Err("Failed to serialize config".to_string()),
+    };
+
+    for (key, value) in changes {
+        if key == VERSION_KEY {
+            return Err("The config version is managed by Velt".to_string());
+        }
+        if !values.contains_key(key) {
+            return Err(format!("Unknown setting '{}'", key));
+        }
+        values.insert(key.clone(), value.clone());
+        // Checked one by one, so the error names the setting
+        serde_json::from_value::<AppConfig>(Value::Object(values.clone()))
+            .map_err(|e| format!("Invalid value for '{}': {}", key, e))?;
+    }
+
+    serde_json::from_value(Value::Object(values)).map_err(|e| e.to_string())
+}
+
+/// Change one setting in the user config. The value must have the setting's
+/// type; other settings are left as they are, even if invalid.
+pub fn set_config_value(key: &str, value: Value) -> Result<(), String> {
+    let changes = Map::from_iter([(key.to_string(), value)]);
+    write_settings(&parse_config()?, &changes)
+}
+
+/// Remove one setting from the user config, so it is inherited again.
+/// Returns whether the user config had it.
+pub fn unset_config_value(key: &str) -> Result<bool, String> {
+    if key == VERSION_KEY {
+        return Err("The config version is managed by Velt".to_string());
+    }
+    if !effective_values()?.contains_key(key) {
+        return Err(format!("Unknown setting '{}'", key));
+    }
+
+    let path = get_user_config_path()?;
+    let mut user = read_layer(&path)?;
+    migrate_layer(&mut user);
+    if user.remove(key).is_none() {
+        return Ok(false);
+    }
+    write_user_layer(&path, user)?;
+    Ok(true)
+}
+
+/// Empty the user config, keeping the previous one next to it as
+/// `config.json.bak`. Returns the backup path, if there was anything to keep.
+pub fn reset_user_config() -> Result<Option<PathBuf>, String> {
+    let path = get_user_config_path()?;
+    let mut backup = None;
+    if path.exists() {
+        let backup_path = path.with_extension("json.bak");
+        fs::copy(&path, &backup_path).map_err(|e| e.to_string())?;
+        backup = Some(backup_path);
+    }
+
+    write_user_layer(&path, Map::new())?;
+    Ok(backup)
+}
+
 /// Load a specific theme from file by name
 pub fn load_theme(theme_name: &str, config: &AppConfig) -> Result<Theme, String> {
     let themes_dir = get_themes_dir(config)?;
@@ -928,6 +1754,11 @@ pub fn load_theme(theme_name: &str, config: &AppConfig) -> Result<Theme, String>
 pub fn load_current_theme(config: &AppConfig) -> Result<Theme, String> {
     let themes_dir = get_themes_dir(config)?;
 
+    // A theme pinned by the system policy wins over the user's current.json
+    if load_locked_settings()?.contains_key("theme") {
+        return load_theme(&config.theme, config);
+    }
+
     // Try current.json first
     let current_path = themes_dir.join("current.json");
     if current_path.exists() {
@@ -941,8 +1772,13 @@ pub fn load_current_theme(config: &AppConfig) -> Result<Theme, String> {
     load_theme(&config.theme, config)
 }
 
-/// Save the current theme to current.json
+/// Save the current theme to current.json. Refused while the system policy
+/// locks the theme.
 pub fn save_current_theme(theme: &Theme) -> Result<(), String> {
+    if load_locked_settings()?.contains_key("theme") {
+        return Err("The theme is locked by your administrator".to_string());
+    }
+
     let config_dir = get_config_dir()?;
     let themes_dir = config_dir.join("themes");
 
@@ -956,12 +1792,39 @@ pub fn save_current_theme(theme: &Theme) -> Result<(), String> {
     Ok(())
 }
 
+/// Parse theme JSON; errors point at the offending line and column
+pub fn parse_theme(content: &str) -> Result<Theme, String> {
+    let theme: Theme = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    if theme.name.trim().is_empty() {
+        return Err("Theme name must not be empty".to_string());
+    }
+    Ok(theme)
+}
+
+/// Theme names become file names: keep them to a safe character set
+pub fn validate_theme_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid theme name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        ))
+    }
+}
+
 /// Save a custom theme with a specific name
 pub fn save_custom_theme(theme_name: &str, theme: &Theme) -> Result<(), String> {
     // Prevent overwriting default themes
     if theme_name == "default-dark" || theme_name == "default-light" || theme_name == "current" {
         return Err("Cannot overwrite default themes".to_string());
     }
+    check_theme_unlocked(theme_name)?;
 
     let config = load_config()?;
     let themes_dir = get_themes_dir(&config)?;
@@ -982,6 +1845,7 @@ pub fn delete_theme(theme_name: &str) -> Result<(), String> {
     if theme_name == "default-dark" || theme_name == "default-light" || theme_name == "current" {
         return Err("Cannot delete default themes".to_string());
     }
+    check_theme_unlocked(theme_name)?;
 
     let config = load_config()?;
     let themes_dir = get_themes_dir(&config)?;
@@ -996,6 +1860,18 @@ pub fn delete_theme(theme_name: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// The theme file pinned by the system policy must stay as it is
+fn check_theme_unlocked(theme_name: &str) -> Result<(), String> {
+    let locked = load_locked_settings()?;
+    if locked.get("theme").and_then(Value::as_str) == Some(theme_name) {
+        return Err(format!(
+            "Theme '{}' is locked by your administrator",
+            theme_name
+        ));
+    }
+    Ok(())
+}
+
 /// List all available themes
 pub fn list_themes(config: &AppConfig) -> Result<Vec<String>, String> {
     let themes_dir = get_themes_dir(config)?;
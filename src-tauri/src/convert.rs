@@ -0,0 +1,117 @@
+use crate::cli::LineEnding;
+use encoding_rs::{UTF_8, WINDOWS_1252};
+use std::fs;
+use std::path::Path;
+
+/// What `velt convert` should make of each file
+pub struct Conversion {
+    /// Target save-encoding label; `None` keeps the file's encoding
+    pub to: Option<String>,
+    /// Encoding to read files without a BOM with, instead of guessing
+    pub from: Option<String>,
+    /// Target line endings; `None` keeps them as they are
+    pub eol: Option<LineEnding>,
+}
+
+pub enum Outcome {
+    Unchanged,
+    /// Converted (or, without writing, to be converted), with a description
+    /// of the change
+    Converted(String),
+    Skipped(String),
+}
+
+/// Convert one file. Without `write`, only report what would change.
+pub fn convert_file(path: &Path, conversion: &Conversion, write: bool) -> Result<Outcome, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+
+    // NUL bytes only occur in text as UTF-16, which has a BOM or a --from
+    let info = crate::detect_encoding(&bytes);
+    if info.skip_bytes == 0 && conversion.from.is_none() && bytes.contains(&0) {
+        return Ok(Outcome::Skipped("binary file".to_string()));
+    }
+
+    let (text, source) = decode_text(&bytes, conversion.from.as_deref())?;
+
+    let target = conversion.to.clone().unwrap_or_else(|| source.clone());
+    let converted = match conversion.eol {
+        Some(eol) => normalize_line_endings(&text, eol),
+        None => text.clone(),
+    };
+    let output = crate::encode_content(&converted, &target)?;
+    if output == bytes {
+        return Ok(Outcome::Unchanged);
+    }
+
+    if write {
+        fs::write(path, &output).map_err(|e| e.to_string())?;
+    }
+    Ok(Outcome::Converted(format!(
+        "{}, {} -> {}, {}",
+        source,
+        describe_line_endings(&text),
+        target,
+        describe_line_endings(&converted)
+    )))
+}
+
+/// Decode text by its BOM, or else as `decode_without_bom` does. Returns the
+/// text and the save-encoding label it was read with.
+pub fn decode_text(bytes: &[u8], from: Option<&str>) -> Result<(String, String), String> {
+    let info = crate::detect_encoding(bytes);
+    if info.skip_bytes == 0 {
+        return decode_without_bom(bytes, from);
+    }
+
+    let (text, had_errors) = info
+        .encoding
+        .decode_without_bom_handling(&bytes[info.skip_bytes..]);
+    if had_errors {
+        return Err(format!("Error decoding file as {}", info.name));
+    }
+    Ok((text.into_owned(), info.name))
+}
+
+/// Decode a file without a BOM: with the given encoding, or as UTF-8 when it
+/// is valid UTF-8 and as Windows-1252 (which accepts any byte) otherwise
+fn decode_without_bom(bytes: &[u8], from: Option<&str>) -> Result<(String, String), String> {
+    if let Some(from) = from {
+        let (enc, _) =
+            crate::encoding_for_label(from).ok_or_else(|| format!("Unknown encoding: {}", from))?;
+        let (text, had_errors) = enc.decode_without_bom_handling(bytes);
+        if had_errors {
+            return Err(format!("Error decoding file as {}", from));
+        }
+        return Ok((text.into_owned(), from.to_string()));
+    }
+
+    match UTF_8.decode_without_bom_handling_and_without_replacement(bytes) {
+        Some(text) => Ok((text.into_owned(), "UTF-8".to_string())),
+        None => {
+            let (text, _) = WINDOWS_1252.decode_without_bom_handling(bytes);
+            Ok((text.into_owned(), "WINDOWS-1252".to_string()))
+        }
+    }
+}
+
+fn normalize_line_endings(text: &str, eol: LineEnding) -> String {
+    let lf = text.replace("\r\n", "\n").replace('\r', "\n");
+    match eol {
+        LineEnding::Lf => lf,
+        LineEnding::Crlf => lf.replace('\n', "\r\n"),
+    }
+}
+
+fn describe_line_endings(text: &str) -> &'static str {
+    let crlf = text.matches("\r\n").count();
+    let cr = text.matches('\r').count() - crlf;
+    let lf = text.matches('\n').count() - crlf;
+
+    match (crlf > 0, lf > 0, cr > 0) {
+        (false, false, false) => "no line breaks",
+        (true, false, false) => "CRLF",
+        (false, true, false) => "LF",
+        (false, false, true) => "CR",
+        _ => "mixed line endings",
+    }
+}
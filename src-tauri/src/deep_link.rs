@@ -0,0 +1,131 @@
+use crate::cli::CliFile;
+use std::path::{Component, Path};
+
+const SCHEME: &str = "velt://";
+const FILE_SCHEME: &str = "file://";
+
+/// Whether a command line argument is a URL rather than a file path: a
+/// `velt://` link, or a `file://` URL as passed by desktop environments
+pub fn is_url(arg: &str) -> bool {
+    has_prefix(arg, SCHEME) || has_prefix(arg, FILE_SCHEME)
+}
+
+fn has_prefix(arg: &str, prefix: &str) -> bool {
+    arg.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// Parse a `velt://open?path=...&line=...&column=...` link or a
+/// `file:///path` URL into the file it opens. Files of links are marked,
+/// to be confirmed before opening outside the known workspaces.
+pub fn parse(url: &str) -> Result<CliFile, String> {
+    let invalid = |reason: &str| format!("Invalid URL '{}': {}", url, reason);
+
+    if has_prefix(url, FILE_SCHEME) {
+        // Only local files: file:///path or file://localhost/path
+        let rest = &url[FILE_SCHEME.len()..];
+        let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+        if !rest.starts_with('/') {
+            return Err(invalid("only local files can be opened"));
+        }
+        let path = percent_decode(rest, false).map_err(|e| invalid(&e))?;
+        return file_at(path, None, None).map_err(|e| invalid(&e));
+    }
+
+    let rest = url
+        .get(SCHEME.len()..)
+        .filter(|_| has_prefix(url, SCHEME))
+        .ok_or_else(|| invalid("expected velt://"))?;
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (action, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if action.trim_end_matches('/') != "open" {
+        return Err(invalid(&format!("unknown action '{}'", action)));
+    }
+
+    let (mut path, mut line, mut column) = (None, None, None);
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value, true).map_err(|e| invalid(&e))?;
+        let slot = match key {
+            "path" => &mut path,
+            "line" => &mut line,
+            "column" => &mut column,
+            _ => return Err(invalid(&format!("unknown parameter '{}'", key))),
+        };
+        if slot.replace(value).is_some() {
+            return Err(invalid(&format!("'{}' is given more than once", key)));
+        }
+    }
+
+    let number = |name: &str, value: Option<String>| match value {
+        None => Ok(None),
+        Some(value) => match value.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(invalid(&format!("{} must be a positive number", name))),
+        },
+    };
+    let line = number("line", line)?;
+    let column = number("column", column)?;
+    if column.is_some() && line.is_none() {
+        return Err(invalid("column requires line"));
+    }
+
+    let path = path.ok_or_else(|| invalid("missing path"))?;
+    let file = file_at(path, line, column).map_err(|e| invalid(&e))?;
+    Ok(CliFile {
+        from_link: true,
+        ..file
+    })
+}
+
+/// Links come from outside: only accept plain absolute paths
+fn file_at(path: String, line: Option<u32>, column: Option<u32>) -> Result<CliFile, String> {
+    if path.contains('\0') {
+        return Err("path contains a NUL byte".to_string());
+    }
+    let p = Path::new(&path);
+    if !p.is_absolute() {
+        return Err("path must be absolute".to_string());
+    }
+    if p.components()
+        .any(|c| matches!(c, Component::ParentDir | Component::CurDir))
+    {
+        return Err("path must not contain '.' or '..'".to_string());
+    }
+
+    Ok(CliFile {
+        path,
+        line,
+        column,
+        ..CliFile::default()
+    })
+}
+
+/// Decode `%XX` escapes, and `+` as a space in query values
+fn percent_decode(input: &str, plus_as_space: bool) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = input
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or("malformed %-escape")?;
+                decoded.push(hex);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| "escapes do not decode to UTF-8".to_string())
+}
@@ -0,0 +1,341 @@
+use crate::config;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// Findings of `velt doctor`, printed as they are made
+struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn section(&self, title: &str) {
+        println!("\n{}", title);
+    }
+
+    fn add(&mut self, status: Status, message: impl AsRef<str>) {
+        let tag = match status {
+            Status::Ok => "ok",
+            Status::Warning => {
+                self.warnings += 1;
+                "warn"
+            }
+            Status::Error => {
+                self.errors += 1;
+                "error"
+            }
+        };
+        println!("  [{:<5}] {}", tag, message.as_ref());
+    }
+
+    /// Context for the findings, without a status
+    fn info(&self, message: impl AsRef<str>) {
+        println!("          {}", message.as_ref());
+    }
+
+    fn check(&mut self, result: Result<String, String>) {
+        match result {
+            Ok(message) => self.add(Status::Ok, message),
+            Err(message) => self.add(Status::Error, message),
+        }
+    }
+}
+
+/// Check the environment Velt runs in: directories, config, themes, fonts
+/// and display. Returns whether no errors were found.
+pub fn run() -> bool {
+    let mut report = Report {
+        errors: 0,
+        warnings: 0,
+    };
+    println!("Velt {} doctor", env!("CARGO_PKG_VERSION"));
+
+    check_directories(&mut report);
+    check_config(&mut report);
+    check_themes(&mut report);
+    check_fonts(&mut report);
+    check_display(&mut report);
+
+    println!(
+        "\n{} error(s), {} warning(s)",
+        report.errors, report.warnings
+    );
+    report.errors == 0
+}
+
+/// Where a directory comes from: its environment variable, or the platform default
+fn origin(var: &str) -> String {
+    match env::var(var) {
+        Ok(_) => format!("from {}", var),
+        Err(_) => "platform default".to_string(),
+    }
+}
+
+/// Check that a directory exists (or can be created) and is writable
+fn check_writable_dir(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("cannot be created: {}", e))?;
+    let probe = dir.join(".velt-doctor");
+    fs::write(&probe, b"").map_err(|e| format!("not writable: {}", e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+fn check_dir(report: &mut Report, name: &str, dir: Result<PathBuf, String>, origin: String) {
+    report.check(dir.and_then(|dir| match check_writable_dir(&dir) {
+        Ok(()) => Ok(format!("{}: {} ({})", name, dir.display(), origin)),
+        Err(e) => Err(format!("{}: {} ({}) {}", name, dir.display(), origin, e)),
+    }));
+}
+
+fn check_directories(report: &mut Report) {
+    report.section("Directories");
+    check_dir(
+        report,
+        "Config",
+        config::get_config_dir(),
+        origin("VELT_CONFIG_HOME"),
+    );
+    check_dir(
+        report,
+        "State",
+        config::get_state_dir(),
+        origin("VELT_STATE_HOME"),
+    );
+
+    // An invalid config is reported below; its themes are checked with the defaults
+    let app_config = config::load_config().unwrap_or_default();
+    let origin = match app_config.themes_dir {
+        Some(_) => "from themesDir".to_string(),
+        None => "in the config directory".to_string(),
+    };
+    check_dir(
+        report,
+        "Themes",
+        config::get_themes_dir(&app_config),
+        origin,
+    );
+}
+
+/// Check that a config layer file, if present, is a JSON object
+fn check_layer_file(report: &mut Report, name: &str, path: &Path) {
+    if !path.exists() {
+        report.add(
+            Status::Ok,
+            format!("{}: {} (not present)", name, path.display()),
+        );
+        return;
+    }
+
+    let parsed = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            serde_json::from_str::<serde_json::Value>(&content).map_err(|e| e.to_string())
+        });
+    match parsed {
+        Ok(serde_json::Value::Object(_)) => {
+            report.add(Status::Ok, format!("{}: {}", name, path.display()))
+        }
+        Ok(_) => report.add(
+            Status::Error,
+            format!("{}: {}: expected a JSON object", name, path.display()),
+        ),
+        Err(e) => report.add(
+            Status::Error,
+            format!("{}: {}: {}", name, path.display(), e),
+        ),
+    }
+}
+
+fn check_config(report: &mut Report) {
+    report.section("Config");
+
+    check_layer_file(report, "System config", &config::get_system_config_path());
+    match config::get_user_config_path() {
+        Ok(path) => check_layer_file(report, "User config", &path),
+        Err(e) => report.add(Status::Error, format!("User config: {}", e)),
+    }
+    if let Some(root) = env::current_dir()
+        .ok()
+        .and_then(|cwd| config::find_workspace_root(&cwd))
+    {
+        config::set_workspace_root(Some(root));
+        if let Some(path) = config::get_workspace_config_path() {
+            check_layer_file(report, "Workspace settings", &path);
+        }
+    }
+    check_layer_file(report, "Policy", &config::get_policy_path());
+
+    // Layers can each be valid JSON and still merge into wrong types
+    report.check(
+        config::load_config()
+            .map(|_| "Effective config loads".to_string())
+            .map_err(|e| format!("Effective config is invalid: {}", e)),
+    );
+    // Loaded anyway, with the defaults in their place
+    for problem in config::config_problems().unwrap_or_default() {
+        report.add(Status::Warning, format!("{}, the default is used", problem));
+    }
+}
+
+fn check_themes(report: &mut Report) {
+    report.section("Themes");
+
+    let app_config = config::load_config().unwrap_or_default();
+    let themes_dir = match config::get_themes_dir(&app_config) {
+        Ok(dir) => dir,
+        Err(e) => {
+            report.add(Status::Error, e);
+            return;
+        }
+    };
+    let mut themes = match config::list_themes(&app_config) {
+        Ok(themes) => themes,
+        Err(e) => {
+            report.add(Status::Error, format!("Cannot list themes: {}", e));
+            return;
+        }
+    };
+    themes.sort();
+
+    for name in &themes {
+        let path = themes_dir.join(format!("{}.json", name));
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| config::parse_theme(&content));
+        match parsed {
+            Ok(theme) => report.add(Status::Ok, format!("{} ({})", name, theme.name)),
+            Err(e) => report.add(
+                Status::Error,
+                format!("{}: {}: {}", name, path.display(), e),
+            ),
+        }
+    }
+
+    if !themes.contains(&app_config.theme) {
+        report.add(
+            Status::Error,
+            format!("Startup theme '{}' does not exist", app_config.theme),
+        );
+    }
+}
+
+fn check_fonts(report: &mut Report) {
+    report.section("Fonts");
+
+    #[cfg(target_os = "linux")]
+    {
+        let sandboxed = env::var("FLATPAK_ID").is_ok() || Path::new("/.flatpak-info").exists();
+        match std::process::Command::new("fc-list")
+            .arg(":")
+            .arg("family")
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                let families = String::from_utf8_lossy(&output.stdout).lines().count();
+                report.add(
+                    Status::Ok,
+                    format!("fc-list found {} font families", families),
+                );
+            }
+            Ok(output) => report.add(
+                Status::Warning,
+                format!(
+                    "fc-list failed ({}): the font list falls back to common fonts",
+                    output.status
+                ),
+            ),
+            Err(e) => report.add(
+                Status::Warning,
+                format!(
+                    "fc-list not available ({}){}: the font list falls back to common fonts",
+                    e,
+                    if sandboxed {
+                        ", running in Flatpak"
+                    } else {
+                        ""
+                    }
+                ),
+            ),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    report.add(
+        Status::Ok,
+        "System fonts are listed with the platform tools",
+    );
+
+    if let Ok(dir) = config::get_config_dir() {
+        let dir = dir.join("fonts");
+        let count = fs::read_dir(&dir)
+            .map(|entries| entries.count())
+            .unwrap_or(0);
+        report.add(
+            Status::Ok,
+            format!("{} imported font(s) in {}", count, dir.display()),
+        );
+    }
+}
+
+fn check_display(report: &mut Report) {
+    report.section("Display");
+
+    #[cfg(target_os = "linux")]
+    {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        for name in [
+            "WAYLAND_DISPLAY",
+            "DISPLAY",
+            "XDG_SESSION_TYPE",
+            "GDK_BACKEND",
+        ] {
+            let value = var(name).unwrap_or_else(|| "(unset)".to_string());
+            report.info(format!("{}={}", name, value));
+        }
+
+        let wayland = var("WAYLAND_DISPLAY").is_some();
+        let backend = match var("GDK_BACKEND") {
+            Some(backend) => Some(format!("{} (from GDK_BACKEND)", backend)),
+            None if wayland => Some("wayland".to_string()),
+            None if var("DISPLAY").is_some() => Some("x11".to_string()),
+            None => None,
+        };
+        match backend {
+            Some(backend) => report.add(Status::Ok, format!("Display backend: {}", backend)),
+            None => report.add(
+                Status::Error,
+                "No display: neither WAYLAND_DISPLAY nor DISPLAY is set",
+            ),
+        }
+
+        // Set by main.rs on Wayland, unless already set
+        match var("WEBKIT_DISABLE_DMABUF_RENDERER") {
+            Some(value) => report.add(
+                Status::Ok,
+                format!(
+                    "WEBKIT_DISABLE_DMABUF_RENDERER={} (from the environment)",
+                    value
+                ),
+            ),
+            None if wayland => report.add(
+                Status::Ok,
+                "WEBKIT_DISABLE_DMABUF_RENDERER=1 (set by Velt on Wayland, avoids blank windows)",
+            ),
+            None => report.add(
+                Status::Ok,
+                "WEBKIT_DISABLE_DMABUF_RENDERER not needed outside Wayland",
+            ),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    report.add(Status::Ok, "Native windowing, no environment needed");
+}
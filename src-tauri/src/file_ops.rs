@@ -0,0 +1,351 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// A completed workspace file operation, recorded so it can be undone
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum FileOperation {
+    #[serde(rename = "create")]
+    Create { path: String },
+    #[serde(rename = "move")]
+    Move { from: String, to: String },
+    #[serde(rename = "duplicate")]
+    Duplicate { source: String, copy: String },
+    #[serde(rename = "trash")]
+    Trash { path: String },
+}
+
+impl FileOperation {
+    /// Path produced by the operation: the new location of a move, or the copy of a duplicate
+    pub fn result_path(&self) -> &str {
+        match self {
+            FileOperation::Create { path } | FileOperation::Trash { path } => path,
+            FileOperation::Move { to, .. } => to,
+            FileOperation::Duplicate { copy, .. } => copy,
+        }
+    }
+}
+
+/// Create an empty file, failing if anything already exists at `path`
+pub fn create_file(path: &Path) -> Result<FileOperation, String> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+
+    Ok(FileOperation::Create {
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+pub fn create_folder(path: &Path) -> Result<FileOperation, String> {
+    fs::create_dir(path).map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+
+    Ok(FileOperation::Create {
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+/// Rename a file or folder in place; `new_name` must be a plain file name
+pub fn rename(path: &Path, new_name: &str) -> Result<FileOperation, String> {
+    if new_name.is_empty()
+        || new_name == "."
+        || new_name == ".."
+        || new_name.contains(['/', std::path::MAIN_SEPARATOR])
+    {
+        return Err(format!("Invalid name: '{}'", new_name));
+    }
+
+    let parent = path.parent().ok_or("Cannot rename a root directory")?;
+    move_to(path, &parent.join(new_name))
+}
+
+/// Move a file or folder into another directory, keeping its name
+pub fn move_into(path: &Path, target_dir: &Path) -> Result<FileOperation, String> {
+    if !target_dir.is_dir() {
+        return Err(format!("'{}' is not a directory", target_dir.display()));
+    }
+    if target_dir.starts_with(path) {
+        return Err("Cannot move a folder into itself".to_string());
+    }
+
+    let name = path.file_name().ok_or("Invalid path")?;
+    move_to(path, &target_dir.join(name))
+}
+
+/// Move `from` to exactly `to`, never overwriting an existing entry
+pub fn move_to(from: &Path, to: &Path) -> Result<FileOperation, String> {
+    if !entry_exists(from) {
+        return Err(format!("'{}' does not exist", from.display()));
+    }
+
+    match rename_no_replace(from, to) {
+        Ok(()) => {}
+        // Renaming fails across filesystems: fall back to copy and delete
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            move_across_devices(from, to)?;
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(format!("'{}' already exists", to.display()));
+        }
+        Err(e) => return Err(format!("Failed to move '{}': {}", from.display(), e)),
+    }
+
+    Ok(FileOperation::Move {
+        from: from.to_string_lossy().to_string(),
+        to: to.to_string_lossy().to_string(),
+    })
+}
+
+/// Copy to a temporary name on the target filesystem first, so that a failed
+/// copy never leaves a partial `to` behind, then rename it into place
+fn move_across_devices(from: &Path, to: &Path) -> Result<(), String> {
+    let name = to.file_name().ok_or("Invalid path")?;
+    let temp = to.with_file_name(format!(".{}.velt-move", name.to_string_lossy()));
+
+    if let Err(e) = copy_recursive(from, &temp) {
+        if entry_exists(&temp) {
+            let _ = remove_recursive(&temp);
+        }
+        return Err(e);
+    }
+    if let Err(e) = rename_no_replace(&temp, to) {
+        let _ = remove_recursive(&temp);
+        return Err(match e.kind() {
+            ErrorKind::AlreadyExists => format!("'{}' already exists", to.display()),
+            _ => format!("Failed to move '{}': {}", from.display(), e),
+        });
+    }
+
+    // The data is safe at `to` by now, even if this fails halfway
+    remove_recursive(from)
+}
+
+/// Rename that fails with `AlreadyExists` instead of replacing `to`
+#[cfg(target_os = "linux")]
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let from_c = CString::new(from.as_os_str().as_bytes())?;
+    let to_c = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL-terminated strings for the duration of the call
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            from_c.as_ptr(),
+            libc::AT_FDCWD,
+            to_c.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        return Ok(());
+    }
+
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        // Kernels or filesystems without RENAME_NOREPLACE
+        Some(libc::ENOSYS) | Some(libc::EINVAL) => rename_if_free(from, to),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    rename_if_free(from, to)
+}
+
+/// Best effort where the platform has no atomic check: a race with another
+/// process creating `to` can still lose
+fn rename_if_free(from: &Path, to: &Path) -> io::Result<()> {
+    if entry_exists(to) {
+        return Err(io::Error::from(ErrorKind::AlreadyExists));
+    }
+    fs::rename(from, to)
+}
+
+/// Like `Path::exists`, but true for dangling symlinks too
+fn entry_exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Copy a file or folder next to itself as "name copy", "name copy 2", ...
+pub fn duplicate(path: &Path) -> Result<FileOperation, String> {
+    if !entry_exists(path) {
+        return Err(format!("'{}' does not exist", path.display()));
+    }
+
+    let copy = duplicate_target(path)?;
+    copy_recursive(path, &copy)?;
+
+    Ok(FileOperation::Duplicate {
+        source: path.to_string_lossy().to_string(),
+        copy: copy.to_string_lossy().to_string(),
+    })
+}
+
+/// Move a file or folder to the system trash (freedesktop trash on Linux)
+pub fn trash(path: &Path) -> Result<FileOperation, String> {
+    if !entry_exists(path) {
+        return Err(format!("'{}' does not exist", path.display()));
+    }
+
+    // Recorded as the trash records it, so that undo finds the item
+    let path = resolve_parent(path);
+    trash::delete(&path).map_err(|e| format!("Failed to move to trash: {}", e))?;
+
+    Ok(FileOperation::Trash {
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+/// `path` made absolute with its folder resolved (symlinks, `..`), the entry
+/// itself left as is: it may be a symlink, or already gone
+fn resolve_parent(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or(absolute),
+        _ => absolute,
+    }
+}
+
+/// Revert an operation. Created entries and copies go to the trash rather than
+/// being deleted permanently, in case they were edited in the meantime.
+pub fn undo(operation: &FileOperation) -> Result<(), String> {
+    match operation {
+        FileOperation::Create { path } => trash(Path::new(path)).map(|_| ()),
+        FileOperation::Move { from, to } => move_to(Path::new(to), Path::new(from)).map(|_| ()),
+        FileOperation::Duplicate { copy, .. } => trash(Path::new(copy)).map(|_| ()),
+        FileOperation::Trash { path } => restore_from_trash(Path::new(path)),
+    }
+}
+
+#[cfg(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+))]
+fn restore_from_trash(path: &Path) -> Result<(), String> {
+    // Several trashed items may share the original path; restore the most recent
+    let path = resolve_parent(path);
+    let item = trash::os_limited::list()
+        .map_err(|e| format!("Failed to list trash: {}", e))?
+        .into_iter()
+        .filter(|item| resolve_parent(&item.original_path()) == path)
+        .max_by_key(|item| item.time_deleted)
+        .ok_or_else(|| format!("'{}' is no longer in the trash", path.display()))?;
+
+    trash::os_limited::restore_all([item])
+        .map_err(|e| format!("Failed to restore from trash: {}", e))
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+)))]
+fn restore_from_trash(_path: &Path) -> Result<(), String> {
+    Err("Restoring from trash is not supported on this platform".to_string())
+}
+
+fn duplicate_target(path: &Path) -> Result<PathBuf, String> {
+    let parent = path.parent().ok_or("Invalid path")?;
+    let is_file = path.is_file();
+
+    // Keep the extension of files: "notes copy.txt" rather than "notes.txt copy"
+    let (stem, extension) = match (is_file, path.file_stem(), path.extension()) {
+        (true, Some(stem), Some(ext)) => (
+            stem.to_string_lossy().to_string(),
+            format!(".{}", ext.to_string_lossy()),
+        ),
+        _ => (
+            path.file_name()
+                .ok_or("Invalid path")?
+                .to_string_lossy()
+                .to_string(),
+            String::new(),
+        ),
+    };
+
+    (1..)
+        .map(|n| match n {
+            1 => parent.join(format!("{} copy{}", stem, extension)),
+            n => parent.join(format!("{} copy {}{}", stem, n, extension)),
+        })
+        .find(|candidate| !entry_exists(candidate))
+        .ok_or_else(|| "Could not find a free name".to_string())
+}
+
+/// Copy without following symlinks (links are copied as links) and without
+/// overwriting anything at `to`
+fn copy_recursive(from: &Path, to: &Path) -> Result<(), String> {
+    let file_type = fs::symlink_metadata(from)
+        .map_err(|e| format!("Failed to read '{}': {}", from.display(), e))?
+        .file_type();
+
+    if file_type.is_symlink() {
+        copy_symlink(from, to)
+            .map_err(|e| format!("Failed to copy '{}': {}", from.display(), e))?;
+    } else if file_type.is_dir() {
+        fs::create_dir(to).map_err(|e| format!("Failed to create '{}': {}", to.display(), e))?;
+        let entries = fs::read_dir(from).map_err(|e| e.to_string())?;
+        for entry in entries {
+            let entry = entry.map_err(|e| e.to_string())?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        copy_file(from, to).map_err(|e| format!("Failed to copy '{}': {}", from.display(), e))?;
+    }
+    Ok(())
+}
+
+/// `fs::copy`, but failing if `to` exists
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    io::copy(&mut source, &mut target)?;
+    target.set_permissions(source.metadata()?.permissions())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    let target = fs::read_link(from)?;
+    if fs::metadata(from).is_ok_and(|m| m.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+fn remove_recursive(path: &Path) -> Result<(), String> {
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    if is_dir {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .map_err(|e| format!("Failed to remove '{}': {}", path.display(), e))
+}
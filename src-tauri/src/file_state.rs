@@ -0,0 +1,240 @@
+use crate::session::ViewState;
+use crate::{config, fs_util};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Bump when the database format changes incompatibly
+const FILE_STATE_VERSION: u32 = 1;
+
+/// Least recently used files are forgotten once the database grows past this size
+const MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// Changes are written out once none arrived for this long...
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+/// ...or at the latest this long after the first unsaved one
+const MAX_SAVE_DELAY: Duration = Duration::from_secs(30);
+
+/// Folds and bookmarks kept per file
+const MAX_MARKS_PER_FILE: usize = 500;
+
+/// A folded region, as character offsets in the document
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct FoldRange {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// What the editor remembers about a file between openings
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FileState {
+    #[serde(rename = "viewState", default, skip_serializing_if = "Option::is_none")]
+    pub view_state: Option<ViewState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub folds: Vec<FoldRange>,
+    /// Bookmarked lines (1-based)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<usize>,
+    /// Encoding chosen by the user instead of the detected one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Language chosen by the user instead of the one guessed from the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(rename = "noteMode", default)]
+    pub note_mode: bool,
+    /// Last access, in seconds since the Unix epoch; drives eviction
+    #[serde(rename = "lastUsed", default)]
+    pub last_used: u64,
+}
+
+/// Per-file state of every recently opened file, keyed by canonical path
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileStateDb {
+    version: u32,
+    files: HashMap<String, FileState>,
+    /// Changed since it was last written out
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Default for FileStateDb {
+    fn default() -> Self {
+        Self {
+            version: FILE_STATE_VERSION,
+            files: HashMap::new(),
+            dirty: false,
+        }
+    }
+}
+
+/// The database shared by all windows. Changes are kept in memory and
+/// written out in batches, once they stop arriving.
+pub struct FileStates {
+    db: Arc<Mutex<FileStateDb>>,
+    changes: mpsc::Sender<()>,
+}
+
+impl FileStates {
+    pub fn load() -> Self {
+        let db = Arc::new(Mutex::new(FileStateDb::load()));
+        let (changes, received) = mpsc::channel();
+
+        let saved = Arc::clone(&db);
+        std::thread::spawn(move || save_when_quiet(&saved, &received));
+
+        Self { db, changes }
+    }
+
+    pub fn get(&self, path: &str) -> Result<Option<FileState>, String> {
+        Ok(self.db.lock().map_err(|e| e.to_string())?.get(path))
+    }
+
+    pub fn set(&self, path: &str, state: FileState) -> Result<(), String> {
+        self.db.lock().map_err(|e| e.to_string())?.set(path, state);
+        let _ = self.changes.send(());
+        Ok(())
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        self.db.lock().map_err(|e| e.to_string())?.rename(from, to);
+        let _ = self.changes.send(());
+        Ok(())
+    }
+
+    /// Write out pending changes now, e.g. before the app exits
+    pub fn flush(&self) -> Result<(), String> {
+        self.db.lock().map_err(|e| e.to_string())?.save_pending()
+    }
+}
+
+/// Save the database once changes stop arriving (or after MAX_SAVE_DELAY)
+fn save_when_quiet(db: &Mutex<FileStateDb>, changes: &mpsc::Receiver<()>) {
+    while changes.recv().is_ok() {
+        let first_change = Instant::now();
+        loop {
+            match changes.recv_timeout(SAVE_DELAY) {
+                Ok(()) if first_change.elapsed() < MAX_SAVE_DELAY => continue,
+                Ok(()) | Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        if let Ok(mut db) = db.lock() {
+            if let Err(e) = db.save_pending() {
+                eprintln!("Failed to save file state: {}", e);
+            }
+        }
+    }
+}
+
+impl FileStateDb {
+    /// Load the database; a missing or invalid file starts an empty one
+    fn load() -> Self {
+        let Ok(path) = get_file_state_path() else {
+            return Self::default();
+        };
+        fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Self>(&content).ok())
+            .filter(|db| db.version == FILE_STATE_VERSION)
+            .unwrap_or_default()
+    }
+
+    fn get(&mut self, path: &str) -> Option<FileState> {
+        let state = self.files.get_mut(&state_key(path))?;
+        // Only marked in memory: persisted with the next change
+        state.last_used = now();
+        Some(state.clone())
+    }
+
+    fn set(&mut self, path: &str, mut state: FileState) {
+        state.folds.truncate(MAX_MARKS_PER_FILE);
+        state.bookmarks.truncate(MAX_MARKS_PER_FILE);
+        state.last_used = now();
+
+        self.files.insert(state_key(path), state);
+        self.dirty = true;
+    }
+
+    /// Carry the state of a renamed or moved file (or of everything below a
+    /// moved folder) over to the new path
+    fn rename(&mut self, from: &str, to: &str) {
+        let from = state_key(from);
+        let to = state_key(to);
+        let prefix = format!("{}{}", from, std::path::MAIN_SEPARATOR);
+
+        let moved: Vec<String> = self
+            .files
+            .keys()
+            .filter(|key| **key == from || key.starts_with(&prefix))
+            .cloned()
+            .collect();
+        if moved.is_empty() {
+            return;
+        }
+
+        for key in moved {
+            if let Some(state) = self.files.remove(&key) {
+                let new_key = format!("{}{}", to, &key[from.len()..]);
+                self.files.insert(new_key, state);
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Keep the most recently used files that fit in MAX_BYTES
+    fn evict(&mut self) {
+        let mut by_age: Vec<(u64, usize, String)> = self
+            .files
+            .iter()
+            .map(|(key, state)| {
+                // Key and value as written, with their quotes, colon and comma
+                let size = serde_json::to_string(state).map_or(0, |json| json.len());
+                (state.last_used, key.len() + size + 4, key.clone())
+            })
+            .collect();
+        by_age.sort_by_key(|(last_used, _, _)| std::cmp::Reverse(*last_used));
+
+        let mut total = 0;
+        for (_, size, key) in by_age {
+            total += size;
+            if total > MAX_BYTES {
+                self.files.remove(&key);
+            }
+        }
+    }
+
+    fn save_pending(&mut self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.evict();
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs_util::write_atomic(&get_file_state_path()?, json.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+pub fn get_file_state_path() -> Result<PathBuf, String> {
+    Ok(config::get_state_dir()?.join("file-state.json"))
+}
+
+/// Files are identified by their canonical path, so `./a.txt` and `/x/a.txt` share state.
+/// Paths that cannot be resolved (e.g. already moved away) are used as given.
+fn state_key(path: &str) -> String {
+    fs::canonicalize(Path::new(path))
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
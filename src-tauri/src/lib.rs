@@ -1,14 +1,60 @@
 use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::process::Command;
 use std::sync::Mutex;
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 
-mod config;
+pub mod cli;
+mod compare;
+pub mod config;
+pub mod convert;
+mod deep_link;
+pub mod doctor;
+mod file_ops;
+mod file_state;
+mod fs_util;
+mod merge;
+mod outline;
+pub mod rpc;
+pub mod session;
+mod symbol_index;
 
-// State to store CLI files to open
-struct CliFilesState(Mutex<Vec<String>>);
+// Files each window should open once loaded, keyed by window label: the
+// command line files for the main window, forwarded files for new windows
+struct CliFilesState(Mutex<HashMap<String, Vec<cli::CliFile>>>);
+
+// How each window should open its pending files, keyed by window label
+struct CliOptionsState(Mutex<HashMap<String, cli::OpenOptions>>);
+
+// Files forwarded by later `velt` invocations to an open window, keyed by
+// window label, until the window takes them
+struct ForwardedFilesState(Mutex<HashMap<String, Vec<OpenFiles>>>);
+
+// Tokens of `velt --wait` invocations, with the label of the window holding their files
+struct WaitState(Mutex<HashMap<String, String>>);
+
+// Symbol indexes of the open workspace folders, keyed by root path
+struct SymbolIndexState(Mutex<HashMap<String, symbol_index::WatchedIndex>>);
+
+// Workspace roots whose index is being built, so that each is indexed once
+struct IndexingRootsState(Mutex<HashSet<String>>);
+
+// Workspace file operations done this session, most recent last, for undo
+struct FileOpsHistory(Mutex<Vec<file_ops::FileOperation>>);
+
+// Remembered cursor, folds, bookmarks and overrides of recently opened files
+struct FileStateDbState(file_state::FileStates);
+
+// Effective config, shared by all windows. Changes go through update_config.
+struct ConfigState(Mutex<config::AppConfig>);
+
+// A corrupt user config moved aside at startup, until a window reports it
+struct ConfigRecoveryState(Mutex<Option<config::ConfigRecovery>>);
+
+// Content each file had when it was last read or written, used as the
+// common ancestor when merging external changes into unsaved edits
+struct BaseContentState(Mutex<HashMap<String, String>>);
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct FileContent {
@@ -58,6 +104,18 @@ fn detect_encoding(bytes: &[u8]) -> EncodingInfo {
     }
 }
 
+/// Encoding of a save-encoding label, and whether a BOM is written with it
+fn encoding_for_label(name: &str) -> Option<(&'static Encoding, bool)> {
+    match name {
+        "UTF-8" => Some((UTF_8, false)),
+        "UTF-8-BOM" => Some((UTF_8, true)),
+        "UTF-16LE" => Some((UTF_16LE, true)), // UTF-16 always has BOM
+        "UTF-16BE" => Some((UTF_16BE, true)), // UTF-16 always has BOM
+        "WINDOWS-1252" | "Windows-1252" | "ANSI" => Some((WINDOWS_1252, false)),
+        _ => None,
+    }
+}
+
 #[tauri::command]
 fn read_file_as_base64(path: String) -> Result<String, String> {
     use base64::{engine::general_purpose, Engine as _};
@@ -65,9 +123,9 @@ fn read_file_as_base64(path: String) -> Result<String, String> {
     Ok(general_purpose::STANDARD.encode(&bytes))
 }
 
-#[tauri::command]
-fn read_file_content(path: String) -> Result<FileContent, String> {
-    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+// Read a file and decode it with the detected encoding
+fn decode_file(path: &str) -> Result<(String, String), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
 
     let encoding_info = detect_encoding(&bytes);
 
@@ -79,94 +137,520 @@ fn read_file_content(path: String) -> Result<FileContent, String> {
         return Err("Error decoding file with detected encoding".to_string());
     }
 
+    Ok((content.into_owned(), encoding_info.name))
+}
+
+// Read a file and decode it with the encoding chosen by the user; a BOM
+// still takes precedence, as it does for any other editor
+fn decode_file_as(path: &str, encoding: &str) -> Result<(String, String), String> {
+    let (enc, _) =
+        encoding_for_label(encoding).ok_or_else(|| format!("Unknown encoding: {}", encoding))?;
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+
+    let detected = detect_encoding(&bytes);
+    if detected.skip_bytes > 0 {
+        let (content, _, had_errors) = detected.encoding.decode(&bytes[detected.skip_bytes..]);
+        if had_errors {
+            return Err(format!("Error decoding file as {}", detected.name));
+        }
+        return Ok((content.into_owned(), detected.name));
+    }
+
+    let (content, _, had_errors) = enc.decode(&bytes);
+    if had_errors {
+        return Err(format!("Error decoding file as {}", encoding));
+    }
+
+    Ok((content.into_owned(), encoding.to_string()))
+}
+
+#[tauri::command]
+fn read_file_content(
+    path: String,
+    encoding: Option<String>,
+    base_state: tauri::State<BaseContentState>,
+) -> Result<FileContent, String> {
+    let (content, encoding) = match encoding {
+        Some(encoding) => decode_file_as(&path, &encoding)?,
+        None => decode_file(&path)?,
+    };
+
+    base_state
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?
+        .insert(path.clone(), content.clone());
+
     Ok(FileContent {
-        content: content.into_owned(),
+        content,
         path,
-        encoding: encoding_info.name,
+        encoding,
     })
 }
 
+/// Encode text under a save-encoding label, with the BOM the label calls for
+fn encode_content(content: &str, encoding_name: &str) -> Result<Vec<u8>, String> {
+    let (enc, add_bom) = encoding_for_label(encoding_name).unwrap_or((UTF_8, false));
+
+    // encoding_rs encodes UTF-16 labels as UTF-8 (per the WHATWG spec), so
+    // UTF-16 is encoded here
+    let encoded_bytes: Vec<u8> = if enc == UTF_16LE {
+        content.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    } else if enc == UTF_16BE {
+        content.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    } else {
+        let (encoded, _, had_errors) = enc.encode(content);
+        if had_errors {
+            return Err(format!(
+                "Error encoding content: some characters cannot be represented in {}",
+                encoding_name
+            ));
+        }
+        encoded.into_owned()
+    };
+
+    // Prepare final bytes with BOM if needed
+    let bom: &[u8] = match enc {
+        _ if !add_bom => &[],
+        _ if enc == UTF_8 => &[0xEF, 0xBB, 0xBF],
+        _ if enc == UTF_16LE => &[0xFF, 0xFE],
+        _ if enc == UTF_16BE => &[0xFE, 0xFF],
+        _ => &[],
+    };
+    Ok([bom, &encoded_bytes].concat())
+}
+
 #[tauri::command]
 fn write_file_content(
     path: String,
     content: String,
     encoding: Option<String>,
+    base_state: tauri::State<BaseContentState>,
 ) -> Result<(), String> {
     let encoding_name = encoding.unwrap_or_else(|| "UTF-8".to_string());
+    write_file(&base_state, path, content, &encoding_name)
+}
+
+/// Save text to a file and record it as the file's merge base
+fn write_file(
+    bases: &BaseContentState,
+    path: String,
+    content: String,
+    encoding_name: &str,
+) -> Result<(), String> {
+    let final_bytes = encode_content(&content, encoding_name)?;
+
+    fs::write(&path, final_bytes).map_err(|e| e.to_string())?;
+
+    bases
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?
+        .insert(path, content);
+
+    Ok(())
+}
+
+/// Merge changes made on disk into the unsaved buffer of a file, using the
+/// content the file was last read or written with as the common base.
+/// The disk version becomes the new base, since the result now includes it.
+#[tauri::command]
+fn merge_with_disk(
+    path: String,
+    buffer: String,
+    base_state: tauri::State<BaseContentState>,
+) -> Result<merge::MergeResult, String> {
+    let (disk, _) = decode_file(&path)?;
+
+    let mut bases = base_state.0.lock().map_err(|e| e.to_string())?;
+    let base = bases
+        .get(&path)
+        .ok_or_else(|| format!("No base content recorded for '{}'", path))?;
+
+    let result = merge::merge(base, &buffer, &disk);
+    bases.insert(path, disk);
+
+    Ok(result)
+}
+
+/// Drop the recorded base content of a file, e.g. when its last tab is closed
+#[tauri::command]
+fn forget_base_content(
+    path: String,
+    base_state: tauri::State<BaseContentState>,
+) -> Result<(), String> {
+    base_state
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?
+        .remove(&path);
+    Ok(())
+}
+
+#[tauri::command]
+fn compare_directories(
+    left: String,
+    right: String,
+    options: Option<compare::CompareOptions>,
+) -> Result<compare::DirectoryComparison, String> {
+    compare::compare_directories(
+        std::path::Path::new(&left),
+        std::path::Path::new(&right),
+        &options.unwrap_or_default(),
+    )
+}
+
+/// Line diff of two files, e.g. a differing pair from `compare_directories`
+#[tauri::command]
+fn diff_files(left: String, right: String) -> Result<Vec<compare::DiffChunk>, String> {
+    let (left_content, _) = decode_file(&left)?;
+    let (right_content, _) = decode_file(&right)?;
+    Ok(compare::diff_texts(&left_content, &right_content))
+}
 
-    let (enc, add_bom): (&'static Encoding, bool) = match encoding_name.as_str() {
-        "UTF-8" => (UTF_8, false),
-        "UTF-8-BOM" => (UTF_8, true),
-        "UTF-16LE" => (UTF_16LE, true), // UTF-16 always has BOM
-        "UTF-16BE" => (UTF_16BE, true), // UTF-16 always has BOM
-        "WINDOWS-1252" | "Windows-1252" | "ANSI" => (WINDOWS_1252, false),
-        _ => (UTF_8, false),
+/// Hierarchical symbol outline of a document, read from `path` unless `text`
+/// (e.g. an unsaved buffer) is given. The language is guessed from the path
+/// extension when not specified.
+#[tauri::command]
+fn document_outline(
+    path: Option<String>,
+    text: Option<String>,
+    language: Option<String>,
+) -> Result<Vec<outline::DocumentSymbol>, String> {
+    let lang = match (&language, &path) {
+        (Some(name), _) => outline::OutlineLanguage::from_name(name),
+        (None, Some(path)) => outline::OutlineLanguage::from_path(std::path::Path::new(path)),
+        (None, None) => None,
+    }
+    .ok_or_else(|| {
+        format!(
+            "Outline not supported for language '{}'",
+            language.as_deref().unwrap_or("unknown")
+        )
+    })?;
+
+    let source = match (text, path) {
+        (Some(text), _) => text,
+        (None, Some(path)) => decode_file(&path)?.0,
+        (None, None) => return Err("Either a path or text is required".to_string()),
     };
 
-    let (encoded_bytes, _, had_errors) = enc.encode(&content);
+    outline::document_outline(&source, lang)
+}
 
-    if had_errors {
-        return Err("Error encoding content".to_string());
+/// Index the symbols of a workspace folder and keep the index updated while it is open
+#[tauri::command]
+async fn open_workspace_index(app: tauri::AppHandle, root: String) -> Result<(), String> {
+    // The initial indexing of a large folder can take a while
+    tauri::async_runtime::spawn_blocking(move || open_index(&app, root))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Index a workspace folder unless it is open or being indexed already.
+/// Blocks until the index is built.
+fn open_index(app: &tauri::AppHandle, root: String) -> Result<(), String> {
+    let indexes = app.state::<SymbolIndexState>();
+    let indexing = app.state::<IndexingRootsState>();
+    {
+        let indexes = indexes.0.lock().map_err(|e| e.to_string())?;
+        let mut indexing = indexing.0.lock().map_err(|e| e.to_string())?;
+        if indexes.contains_key(&root) || !indexing.insert(root.clone()) {
+            return Ok(());
+        }
     }
 
-    // Prepare final bytes with BOM if needed
-    let final_bytes: Vec<u8> = if add_bom {
-        match enc {
-            _ if enc == UTF_8 => {
-                // UTF-8 BOM: 0xEF 0xBB 0xBF
-                let mut bytes_with_bom = vec![0xEF, 0xBB, 0xBF];
-                bytes_with_bom.extend_from_slice(&encoded_bytes);
-                bytes_with_bom
-            }
-            _ if enc == UTF_16LE => {
-                // UTF-16LE BOM: 0xFF 0xFE
-                let mut bytes_with_bom = vec![0xFF, 0xFE];
-                bytes_with_bom.extend_from_slice(&encoded_bytes);
-                bytes_with_bom
-            }
-            _ if enc == UTF_16BE => {
-                // UTF-16BE BOM: 0xFE 0xFF
-                let mut bytes_with_bom = vec![0xFE, 0xFF];
-                bytes_with_bom.extend_from_slice(&encoded_bytes);
-                bytes_with_bom
+    // Built outside the lock, so that searches are not held up meanwhile
+    let watched = symbol_index::watch(std::path::Path::new(&root));
+
+    let mut indexes = indexes.0.lock().map_err(|e| e.to_string())?;
+    indexing.0.lock().map_err(|e| e.to_string())?.remove(&root);
+    indexes.insert(root, watched?);
+    Ok(())
+}
+
+#[tauri::command]
+fn close_workspace_index(
+    root: String,
+    state: tauri::State<SymbolIndexState>,
+) -> Result<(), String> {
+    state.0.lock().map_err(|e| e.to_string())?.remove(&root);
+    Ok(())
+}
+
+/// Fuzzy "go to symbol in workspace" across all open workspace folders
+#[tauri::command]
+fn search_workspace_symbols(
+    query: String,
+    limit: Option<usize>,
+    state: tauri::State<SymbolIndexState>,
+) -> Result<Vec<symbol_index::SymbolMatch>, String> {
+    let limit = limit.unwrap_or(100);
+    let workspaces = state.0.lock().map_err(|e| e.to_string())?;
+
+    let mut matches = Vec::new();
+    for watched in workspaces.values() {
+        let index = watched.index.lock().map_err(|e| e.to_string())?;
+        matches.extend(index.search(&query, limit));
+    }
+
+    matches.sort_by_key(|m| std::cmp::Reverse(m.score));
+    matches.truncate(limit);
+    Ok(matches)
+}
+
+/// Definitions of a symbol by exact name, for a simple "go to definition"
+#[tauri::command]
+fn find_symbol_definitions(
+    name: String,
+    state: tauri::State<SymbolIndexState>,
+) -> Result<Vec<symbol_index::IndexedSymbol>, String> {
+    let workspaces = state.0.lock().map_err(|e| e.to_string())?;
+
+    let mut definitions = Vec::new();
+    for watched in workspaces.values() {
+        let index = watched.index.lock().map_err(|e| e.to_string())?;
+        definitions.extend(index.definitions(&name));
+    }
+    Ok(definitions)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct PathMoved {
+    from: String,
+    to: String,
+}
+
+// Record a completed file operation and tell every window about moved paths,
+// so tabs showing a moved file (or a file inside a moved folder) can follow it
+fn record_file_operation(
+    app: &tauri::AppHandle,
+    history: &FileOpsHistory,
+    operation: file_ops::FileOperation,
+) -> Result<(), String> {
+    if let file_ops::FileOperation::Move { from, to } = &operation {
+        notify_path_moved(app, from, to);
+    }
+    history.0.lock().map_err(|e| e.to_string())?.push(operation);
+    Ok(())
+}
+
+fn notify_path_moved(app: &tauri::AppHandle, from: &str, to: &str) {
+    let _ = app.emit(
+        "path-moved",
+        PathMoved {
+            from: from.to_string(),
+            to: to.to_string(),
+        },
+    );
+
+    if let Err(e) = app.state::<FileStateDbState>().0.rename(from, to) {
+        eprintln!("Failed to update file state: {}", e);
+    }
+
+    // Keep the merge base of open files (or of the files below a moved folder)
+    if let Ok(mut bases) = app.state::<BaseContentState>().0.lock() {
+        let prefix = format!("{}{}", from, std::path::MAIN_SEPARATOR);
+        let moved: Vec<String> = bases
+            .keys()
+            .filter(|path| *path == from || path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in moved {
+            if let Some(base) = bases.remove(&path) {
+                bases.insert(format!("{}{}", to, &path[from.len()..]), base);
             }
-            _ => encoded_bytes.to_vec(),
         }
-    } else {
-        encoded_bytes.to_vec()
+    }
+}
+
+#[tauri::command]
+fn create_file(
+    path: String,
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<(), String> {
+    let operation = file_ops::create_file(std::path::Path::new(&path))?;
+    record_file_operation(&app, &history, operation)
+}
+
+#[tauri::command]
+fn create_folder(
+    path: String,
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<(), String> {
+    let operation = file_ops::create_folder(std::path::Path::new(&path))?;
+    record_file_operation(&app, &history, operation)
+}
+
+/// Rename a file or folder in place, returning its new path
+#[tauri::command]
+fn rename_path(
+    path: String,
+    new_name: String,
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<String, String> {
+    let operation = file_ops::rename(std::path::Path::new(&path), &new_name)?;
+    let new_path = operation.result_path().to_string();
+    record_file_operation(&app, &history, operation)?;
+    Ok(new_path)
+}
+
+/// Move a file or folder into `target_dir`, returning its new path
+#[tauri::command]
+fn move_path(
+    path: String,
+    target_dir: String,
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<String, String> {
+    let operation = file_ops::move_into(
+        std::path::Path::new(&path),
+        std::path::Path::new(&target_dir),
+    )?;
+    let new_path = operation.result_path().to_string();
+    record_file_operation(&app, &history, operation)?;
+    Ok(new_path)
+}
+
+/// Duplicate a file or folder next to itself, returning the path of the copy
+#[tauri::command]
+fn duplicate_path(
+    path: String,
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<String, String> {
+    let operation = file_ops::duplicate(std::path::Path::new(&path))?;
+    let copy = operation.result_path().to_string();
+    record_file_operation(&app, &history, operation)?;
+    Ok(copy)
+}
+
+#[tauri::command]
+fn trash_path(
+    path: String,
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<(), String> {
+    let operation = file_ops::trash(std::path::Path::new(&path))?;
+    record_file_operation(&app, &history, operation)
+}
+
+/// Undo the most recent file operation of this session, returning it
+/// (or `None` when there is nothing left to undo)
+#[tauri::command]
+fn undo_file_operation(
+    app: tauri::AppHandle,
+    history: tauri::State<FileOpsHistory>,
+) -> Result<Option<file_ops::FileOperation>, String> {
+    let mut operations = history.0.lock().map_err(|e| e.to_string())?;
+    let Some(operation) = operations.last() else {
+        return Ok(None);
     };
 
-    fs::write(&path, final_bytes).map_err(|e| e.to_string())?;
+    file_ops::undo(operation)?;
 
-    Ok(())
+    if let file_ops::FileOperation::Move { from, to } = operation {
+        notify_path_moved(&app, to, from);
+    }
+
+    Ok(operations.pop())
 }
 
+/// Remembered state of a file (cursor, folds, bookmarks, overrides), if it was opened before
 #[tauri::command]
-fn get_config() -> Result<config::AppConfig, String> {
-    config::load_config()
+fn get_file_state(
+    path: String,
+    state: tauri::State<FileStateDbState>,
+) -> Result<Option<file_state::FileState>, String> {
+    state.0.get(&path)
 }
 
 #[tauri::command]
-fn save_app_config(config_data: config::AppConfig) -> Result<(), String> {
-    config::save_config(&config_data)
+fn set_file_state(
+    path: String,
+    file_state: file_state::FileState,
+    state: tauri::State<FileStateDbState>,
+) -> Result<(), String> {
+    state.0.set(&path, file_state)
+}
+
+/// Re-read the effective config from the config layers
+fn reload_config(app: &tauri::AppHandle) -> Result<config::AppConfig, String> {
+    let loaded = config::load_config()?;
+    let state = app.state::<ConfigState>();
+    *state.0.lock().map_err(|e| e.to_string())? = loaded.clone();
+    Ok(loaded)
+}
+
+/// Change some settings, save them and tell every window. `change` gets the
+/// config as it is on disk, so edits made outside the app are kept. The lock
+/// is held throughout, so that changes made at once from several windows
+/// cannot overwrite each other.
+fn update_config(
+    app: &tauri::AppHandle,
+    change: impl FnOnce(
+        &config::AppConfig,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, String>,
+) -> Result<config::AppConfig, String> {
+    let state = app.state::<ConfigState>();
+    let mut current = state.0.lock().map_err(|e| e.to_string())?;
+    *current = config::update_config(change)?;
+    if let Err(e) = app.emit("config-changed", &*current) {
+        eprintln!("Failed to broadcast config change: {}", e);
+    }
+    Ok(current.clone())
+}
+
+/// Current value of the config, for reading without a command round trip
+fn current_config(app: &tauri::AppHandle) -> Result<config::AppConfig, String> {
+    let state = app.state::<ConfigState>();
+    let config = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(config.clone())
 }
 
 #[tauri::command]
-fn get_theme(theme_name: String) -> Result<config::Theme, String> {
-    let config = config::load_config()?;
-    config::load_theme(&theme_name, &config)
+fn get_config(app: tauri::AppHandle) -> Result<config::AppConfig, String> {
+    current_config(&app)
 }
 
+/// Change some settings, leaving the others as they are
 #[tauri::command]
-fn get_current_theme() -> Result<config::Theme, String> {
-    let config = config::load_config()?;
-    config::load_current_theme(&config)
+fn update_app_config(
+    app: tauri::AppHandle,
+    changes: serde_json::Map<String, serde_json::Value>,
+) -> Result<config::AppConfig, String> {
+    update_config(&app, |_| Ok(changes))
 }
 
+/// Every effective config value with the layer (default, system, user,
+/// workspace or cli) it came from
 #[tauri::command]
-fn list_available_themes() -> Result<Vec<String>, String> {
-    let config = config::load_config()?;
-    config::list_themes(&config)
+fn get_config_sources() -> Result<Vec<config::ConfigValueSource>, String> {
+    config::config_sources()
+}
+
+/// Settings locked by the system policy, for the settings UI to disable
+#[tauri::command]
+fn get_locked_settings() -> Result<Vec<String>, String> {
+    Ok(config::load_locked_settings()?.keys().cloned().collect())
+}
+
+#[tauri::command]
+fn get_theme(app: tauri::AppHandle, theme_name: String) -> Result<config::Theme, String> {
+    config::load_theme(&theme_name, &current_config(&app)?)
+}
+
+#[tauri::command]
+fn get_current_theme(app: tauri::AppHandle) -> Result<config::Theme, String> {
+    config::load_current_theme(&current_config(&app)?)
+}
+
+#[tauri::command]
+fn list_available_themes(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    config::list_themes(&current_config(&app)?)
 }
 
 #[tauri::command]
@@ -191,55 +675,72 @@ fn delete_theme(theme_name: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn add_recent_file(file_path: String) -> Result<(), String> {
-    let mut config = config::load_config()?;
+fn add_recent_file(app: tauri::AppHandle, file_path: String) -> Result<(), String> {
+    update_config(&app, |current| {
+        let mut recent_files = current.recent_files.clone();
 
-    // Remove the file if it already exists (to move it to the front)
-    config.recent_files.retain(|p| p != &file_path);
+        // Remove the file if it already exists (to move it to the front)
+        recent_files.retain(|p| p != &file_path);
 
-    // Add to the front of the list
-    config.recent_files.insert(0, file_path);
+        // Add to the front of the list
+        recent_files.insert(0, file_path);
 
-    // Keep only the last 10 files
-    const MAX_RECENT_FILES: usize = 10;
-    if config.recent_files.len() > MAX_RECENT_FILES {
-        config.recent_files.truncate(MAX_RECENT_FILES);
-    }
+        // Keep only the last 10 files
+        const MAX_RECENT_FILES: usize = 10;
+        recent_files.truncate(MAX_RECENT_FILES);
 
-    config::save_config(&config)
+        Ok(serde_json::Map::from_iter([(
+            "recentFiles".to_string(),
+            serde_json::json!(recent_files),
+        )]))
+    })
+    .map(|_| ())
 }
 
 #[tauri::command]
-fn get_recent_files() -> Result<Vec<String>, String> {
-    let config = config::load_config()?;
-    Ok(config.recent_files)
+fn get_recent_files(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    Ok(current_config(&app)?.recent_files)
 }
 
 #[tauri::command]
-fn clear_recent_files() -> Result<(), String> {
-    let mut config = config::load_config()?;
-    config.recent_files.clear();
-    config::save_config(&config)
+fn clear_recent_files(app: tauri::AppHandle) -> Result<(), String> {
+    update_config(&app, |_| {
+        Ok(serde_json::Map::from_iter([(
+            "recentFiles".to_string(),
+            serde_json::json!([]),
+        )]))
+    })
+    .map(|_| ())
 }
 
 #[tauri::command]
 async fn create_new_window(app: tauri::AppHandle) -> Result<(), String> {
-    use tauri::WebviewUrl;
-    use tauri::WebviewWindowBuilder;
+    let window_label = new_window_label();
+
+    // Must be async: on Windows, WebView2 deadlocks when creating
+    // windows from a synchronous command handler due to thread affinity.
+    // Linux (WebKitGTK) and macOS (WKWebView) don't have this constraint.
+    build_editor_window(&app, &window_label)
+}
 
-    // Generate a unique label for the new window
-    let window_label = format!(
-        "window-{}",
+/// Generate a unique label for a new window
+fn new_window_label() -> String {
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+    format!(
+        "window-{}-{}",
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
-            .as_millis()
-    );
+            .as_millis(),
+        NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    )
+}
 
-    // Must be async: on Windows, WebView2 deadlocks when creating
-    // windows from a synchronous command handler due to thread affinity.
-    // Linux (WebKitGTK) and macOS (WKWebView) don't have this constraint.
-    WebviewWindowBuilder::new(&app, window_label, WebviewUrl::default())
+fn build_editor_window(app: &tauri::AppHandle, label: &str) -> Result<(), String> {
+    use tauri::WebviewUrl;
+    use tauri::WebviewWindowBuilder;
+
+    WebviewWindowBuilder::new(app, label, WebviewUrl::default())
         .title("Velt")
         .inner_size(1200.0, 800.0)
         .min_inner_size(800.0, 600.0)
@@ -253,9 +754,349 @@ async fn create_new_window(app: tauri::AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn get_cli_files(state: tauri::State<CliFilesState>) -> Result<Vec<String>, String> {
-    let files = state.0.lock().map_err(|e| e.to_string())?;
-    Ok(files.clone())
+fn save_window_session(
+    window: tauri::Window,
+    session: session::WindowSession,
+    index_state: tauri::State<SymbolIndexState>,
+) -> Result<(), String> {
+    // Workspace folders of the session: the config workspace and indexed folders
+    let mut roots: Vec<String> = index_state
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?
+        .keys()
+        .cloned()
+        .collect();
+    if let Some(root) = config::get_workspace_root() {
+        roots.push(root.to_string_lossy().to_string());
+    }
+    roots.sort();
+    roots.dedup();
+
+    session::save_window_session(window.label(), session, roots)
+}
+
+#[tauri::command]
+fn load_window_session(
+    app: tauri::AppHandle,
+    window: tauri::Window,
+) -> Result<Option<session::WindowSession>, String> {
+    let saved = session::load_window_session(window.label())?;
+    if current_config(&app)?.restore_session {
+        Ok(saved)
+    } else {
+        Ok(saved.and_then(session::hot_exit_only))
+    }
+}
+
+// Async: buffers can be large, keep the write off the main thread
+#[tauri::command]
+async fn store_buffer(tab_id: String, content: String) -> Result<(), String> {
+    session::store_buffer(&tab_id, &content)
+}
+
+#[tauri::command]
+async fn load_buffer(tab_id: String) -> Result<Option<String>, String> {
+    session::load_buffer(&tab_id)
+}
+
+#[tauri::command]
+fn discard_buffer(tab_id: String) -> Result<(), String> {
+    session::discard_buffer(&tab_id)
+}
+
+#[tauri::command]
+fn list_sessions() -> Result<Vec<session::SessionInfo>, String> {
+    session::list_sessions()
+}
+
+#[tauri::command]
+fn delete_session(name: String) -> Result<(), String> {
+    session::delete_session(&name)
+}
+
+#[tauri::command]
+fn get_active_session() -> Option<String> {
+    session::get_active_session()
+}
+
+/// Reopen the workspace indexes of a named session in the background
+fn restore_session_roots(app: &tauri::AppHandle) -> Result<(), String> {
+    if session::get_active_session().is_none() {
+        return Ok(());
+    }
+
+    for root in session::load_session()?.roots {
+        if !std::path::Path::new(&root).is_dir() {
+            continue;
+        }
+        let app = app.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = open_index(&app, root.clone()) {
+                eprintln!("Failed to reopen workspace '{}': {}", root, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Reopen the secondary windows of the previous session; the main window
+/// restores its own tabs once its frontend has loaded. Windows holding
+/// unsaved buffers are reopened even when session restore is turned off.
+fn restore_session_windows(app: &tauri::AppHandle) -> Result<(), String> {
+    let restore_all = current_config(app)?.restore_session;
+
+    let session = session::load_session()?;
+    session::prune_buffers(&session)?;
+
+    for (label, window) in &session.windows {
+        let wanted = restore_all || window.tabs.iter().any(|tab| tab.hot_exit);
+        if wanted && app.get_webview_window(label).is_none() {
+            build_editor_window(app, label)?;
+        }
+    }
+    Ok(())
+}
+
+/// Workspace folders Velt knows of: the open ones, those of the sessions and
+/// the one it was started in
+fn known_workspaces(app: &tauri::AppHandle) -> Vec<std::path::PathBuf> {
+    let mut roots: Vec<String> = Vec::new();
+    if let Ok(indexes) = app.state::<SymbolIndexState>().0.lock() {
+        roots.extend(indexes.keys().cloned());
+    }
+    if let Ok(current) = session::load_session() {
+        roots.extend(current.roots);
+    }
+    for named in session::list_sessions().unwrap_or_default() {
+        roots.extend(named.roots);
+    }
+    if let Some(root) = config::get_workspace_root() {
+        roots.push(root.to_string_lossy().to_string());
+    }
+    roots
+        .iter()
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .collect()
+}
+
+/// Mark the files of `velt://` links outside the known workspaces, for the
+/// frontend to confirm before opening them
+fn flag_untrusted_links(app: &tauri::AppHandle, files: &mut [cli::CliFile]) {
+    if !files.iter().any(|file| file.from_link) {
+        return;
+    }
+    let roots = known_workspaces(app);
+    for file in files.iter_mut().filter(|file| file.from_link) {
+        // A file that does not exist yet is judged by its directory
+        let path = std::path::Path::new(&file.path);
+        let real = fs::canonicalize(path)
+            .ok()
+            .or_else(|| path.parent().and_then(|dir| fs::canonicalize(dir).ok()));
+        file.confirm_open =
+            !real.is_some_and(|real| roots.iter().any(|root| real.starts_with(root)));
+    }
+}
+
+#[tauri::command]
+fn get_cli_files(
+    app: tauri::AppHandle,
+    window: tauri::Window,
+    state: tauri::State<CliFilesState>,
+) -> Result<Vec<cli::CliFile>, String> {
+    let mut files = state
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?
+        .remove(window.label())
+        .unwrap_or_default();
+    flag_untrusted_links(&app, &mut files);
+    Ok(files)
+}
+
+/// Take the report of a corrupt user config replaced at startup, if any.
+/// Only the first window asking gets it.
+#[tauri::command]
+fn take_config_recovery(
+    state: tauri::State<ConfigRecoveryState>,
+) -> Result<Option<config::ConfigRecovery>, String> {
+    Ok(state.0.lock().map_err(|e| e.to_string())?.take())
+}
+
+#[tauri::command]
+fn get_cli_options(
+    window: tauri::Window,
+    state: tauri::State<CliOptionsState>,
+) -> Result<cli::OpenOptions, String> {
+    let mut options = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(options.remove(window.label()).unwrap_or_default())
+}
+
+/// Take the files forwarded to this window by later `velt` invocations, oldest first
+#[tauri::command]
+fn take_open_files(
+    window: tauri::Window,
+    state: tauri::State<ForwardedFilesState>,
+) -> Result<Vec<OpenFiles>, String> {
+    let mut pending = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(pending.remove(window.label()).unwrap_or_default())
+}
+
+/// Let a `velt --wait` invocation return, once its tabs are closed
+#[tauri::command]
+fn release_wait(
+    token: String,
+    output: Option<String>,
+    state: tauri::State<WaitState>,
+) -> Result<(), String> {
+    state.0.lock().map_err(|e| e.to_string())?.remove(&token);
+    cli::release_wait(&token, output.as_deref())
+}
+
+/// Answer a request of the automation API passed on to this window
+#[tauri::command]
+fn rpc_respond(
+    id: u64,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    state: tauri::State<rpc::PendingRequests>,
+) -> Result<(), String> {
+    let result = match error {
+        Some(error) => Err(error),
+        None => Ok(result.unwrap_or(serde_json::Value::Null)),
+    };
+    state.respond(id, result)
+}
+
+/// Tabs of this window, for the automation API to find and report them
+#[tauri::command]
+fn sync_tabs(
+    window: tauri::Window,
+    tabs: Vec<rpc::TabInfo>,
+    state: tauri::State<rpc::WindowTabs>,
+) -> Result<(), String> {
+    state.set(window.label(), tabs)
+}
+
+/// Release the waiting invocations whose files were in a closed window
+fn release_window_waits(app: &tauri::AppHandle, label: &str) {
+    let state = app.state::<WaitState>();
+    let Ok(mut waits) = state.0.lock() else {
+        return;
+    };
+    waits.retain(|token, window| {
+        if window != label {
+            return true;
+        }
+        if let Err(e) = cli::release_wait(token, None) {
+            eprintln!("Failed to release waiting invocation: {}", e);
+        }
+        false
+    });
+}
+
+/// Files forwarded by a second `velt` invocation, opened as tabs in a window
+#[derive(Clone, serde::Serialize)]
+struct OpenFiles {
+    files: Vec<cli::CliFile>,
+    options: cli::OpenOptions,
+    /// `--set` overrides given to the invocation, which a running instance ignores
+    #[serde(rename = "ignoredOverrides", skip_serializing_if = "Vec::is_empty")]
+    ignored_overrides: Vec<String>,
+}
+
+/// Window that files opened from outside go to: the focused one, else the
+/// main one, else any
+fn target_window(app: &tauri::AppHandle) -> Option<tauri::WebviewWindow> {
+    let windows = app.webview_windows();
+    windows
+        .values()
+        .find(|w| w.is_focused().unwrap_or(false))
+        .or_else(|| windows.get("main"))
+        .or_else(|| windows.values().next())
+        .cloned()
+}
+
+/// Queue files for a window to open and tell it. Queued rather than sent
+/// along: the window may not be listening yet, or be busy.
+fn queue_open_files(app: &tauri::AppHandle, label: &str, batch: OpenFiles) {
+    if let Ok(mut pending) = app.state::<ForwardedFilesState>().0.lock() {
+        pending.entry(label.to_string()).or_default().push(batch);
+    }
+    let _ = app.emit_to(label, "open-files", ());
+}
+
+/// Handle a second `velt` invocation: open its files in the focused window,
+/// or in a new window with `--new-window`
+fn handle_second_instance(app: &tauri::AppHandle, args: Vec<String>, cwd: String) {
+    use clap::Parser;
+
+    let cli = match cli::Cli::try_parse_from(&args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Ignoring invalid forwarded arguments: {}", e);
+            return;
+        }
+    };
+    let mut files = cli.resolve_files(std::path::Path::new(&cwd));
+    flag_untrusted_links(app, &mut files);
+    let options = cli.open_options();
+    if !cli.overrides.is_empty() {
+        eprintln!(
+            "Ignoring forwarded --set {}: overrides only apply when Velt starts",
+            cli.overrides.join(" --set ")
+        );
+    }
+
+    let label = match target_window(app) {
+        Some(window) if !cli.new_window => {
+            if !files.is_empty() || options.stdin.is_some() || !cli.overrides.is_empty() {
+                queue_open_files(
+                    app,
+                    window.label(),
+                    OpenFiles {
+                        files,
+                        options: options.clone(),
+                        ignored_overrides: cli.overrides.clone(),
+                    },
+                );
+            }
+            let _ = window.unminimize();
+            let _ = window.set_focus();
+            window.label().to_string()
+        }
+        _ => {
+            let label = new_window_label();
+            if let Ok(mut pending) = app.state::<CliFilesState>().0.lock() {
+                pending.insert(label.clone(), files);
+            }
+            if let Ok(mut pending) = app.state::<CliOptionsState>().0.lock() {
+                pending.insert(label.clone(), options.clone());
+            }
+            if !cli.overrides.is_empty() {
+                queue_open_files(
+                    app,
+                    &label,
+                    OpenFiles {
+                        files: Vec::new(),
+                        options: cli::OpenOptions::default(),
+                        ignored_overrides: cli.overrides.clone(),
+                    },
+                );
+            }
+            if let Err(e) = build_editor_window(app, &label) {
+                eprintln!("{}", e);
+            }
+            label
+        }
+    };
+
+    // Without files to track, the invocation returns when the window closes
+    if let Some(token) = options.wait_token {
+        if let Ok(mut waits) = app.state::<WaitState>().0.lock() {
+            waits.insert(token, label);
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -533,7 +1374,7 @@ fn list_imported_fonts() -> Result<Vec<ImportedFont>, String> {
     }
 
     // Sort alphabetically
-    imported_fonts.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    imported_fonts.sort_by_key(|a| a.name.to_lowercase());
 
     Ok(imported_fonts)
 }
@@ -575,22 +1416,99 @@ fn delete_imported_font(filename: String) -> Result<(), String> {
     Ok(())
 }
 
+/// Forward later invocations to the running instance. Each named session is
+/// its own instance on Linux, where the D-Bus name can be chosen; elsewhere
+/// named sessions do not take part in single-instance mode.
+fn single_instance_plugin() -> tauri::plugin::TauriPlugin<tauri::Wry> {
+    let builder = tauri_plugin_single_instance::Builder::new().callback(handle_second_instance);
+
+    match session::get_active_session() {
+        None => builder.build(),
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            // D-Bus name elements only allow [A-Za-z0-9_]
+            let element: String = name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            builder
+                .dbus_id(format!("com.altagen.velt.session_{}", element))
+                .build()
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => tauri::plugin::Builder::new("single-instance-disabled").build(),
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    run_with_files(Vec::new());
+    run_with_files(Vec::new(), cli::OpenOptions::default());
 }
 
-pub fn run_with_files(files: Vec<String>) {
+pub fn run_with_files(files: Vec<cli::CliFile>, options: cli::OpenOptions) {
+    let waits: HashMap<String, String> = options
+        .wait_token
+        .iter()
+        .map(|token| (token.clone(), "main".to_string()))
+        .collect();
+
     tauri::Builder::default()
+        .plugin(single_instance_plugin())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
-        .manage(CliFilesState(Mutex::new(files)))
+        .manage(CliFilesState(Mutex::new(HashMap::from([(
+            "main".to_string(),
+            files,
+        )]))))
+        .manage(CliOptionsState(Mutex::new(HashMap::from([(
+            "main".to_string(),
+            options,
+        )]))))
+        .manage(ForwardedFilesState(Mutex::new(HashMap::new())))
+        .manage(WaitState(Mutex::new(waits)))
+        .manage(BaseContentState(Mutex::new(HashMap::new())))
+        .manage(rpc::PendingRequests::default())
+        .manage(rpc::WindowTabs::default())
+        .manage(ConfigRecoveryState(Mutex::new(None)))
+        .manage(ConfigState(Mutex::new(config::AppConfig::default())))
+        .manage(SymbolIndexState(Mutex::new(HashMap::new())))
+        .manage(IndexingRootsState(Mutex::new(HashSet::new())))
+        .manage(FileOpsHistory(Mutex::new(Vec::new())))
+        .manage(FileStateDbState(file_state::FileStates::load()))
         .invoke_handler(tauri::generate_handler![
             read_file_as_base64,
             read_file_content,
             write_file_content,
+            merge_with_disk,
+            forget_base_content,
+            compare_directories,
+            diff_files,
+            document_outline,
+            open_workspace_index,
+            close_workspace_index,
+            search_workspace_symbols,
+            find_symbol_definitions,
+            create_file,
+            create_folder,
+            rename_path,
+            move_path,
+            duplicate_path,
+            trash_path,
+            undo_file_operation,
+            get_file_state,
+            set_file_state,
+            save_window_session,
+            load_window_session,
+            store_buffer,
+            load_buffer,
+            discard_buffer,
+            list_sessions,
+            delete_session,
+            get_active_session,
             get_config,
-            save_app_config,
+            update_app_config,
+            get_config_sources,
+            get_locked_settings,
             get_theme,
             get_current_theme,
             list_available_themes,
@@ -603,6 +1521,12 @@ pub fn run_with_files(files: Vec<String>) {
             clear_recent_files,
             create_new_window,
             get_cli_files,
+            get_cli_options,
+            take_open_files,
+            sync_tabs,
+            take_config_recovery,
+            release_wait,
+            rpc_respond,
             list_system_fonts,
             import_font,
             list_imported_fonts,
@@ -610,11 +1534,65 @@ pub fn run_with_files(files: Vec<String>) {
             delete_imported_font,
             get_platform,
         ])
+        .on_window_event(|window, event| {
+            // A window closed while others stay open is gone for good; when the
+            // last one closes the app quits and its session is kept for next time
+            if let tauri::WindowEvent::Destroyed = event {
+                let label = window.label();
+                release_window_waits(window.app_handle(), label);
+                if let Ok(mut pending) = window.state::<ForwardedFilesState>().0.lock() {
+                    pending.remove(label);
+                }
+                window.state::<rpc::WindowTabs>().remove(label);
+                let others_open = window
+                    .app_handle()
+                    .webview_windows()
+                    .keys()
+                    .any(|other| other != label);
+                if others_open {
+                    if let Err(e) = session::remove_window_session(label) {
+                        eprintln!("Failed to update session: {}", e);
+                    }
+                }
+            }
+        })
         .setup(|app| {
+            // Tells `velt session delete` and `velt --wait` that this session is in use
+            if let Err(e) = session::lock_active_session() {
+                eprintln!("Failed to lock session: {}", e);
+            }
+
+            // A config.json that cannot be parsed would make every config
+            // command fail: repair it, and tell the user once a window is up
+            match config::recover_user_config() {
+                Ok(Some(recovery)) => {
+                    eprintln!("{}", recovery.describe());
+                    if let Ok(mut pending) = app.state::<ConfigRecoveryState>().0.lock() {
+                        *pending = Some(recovery);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to recover config: {}", e),
+            }
+
             // Initialize config directory and default files
             if let Err(e) = config::initialize_config() {
                 eprintln!("Failed to initialize config: {}", e);
             }
+            if let Err(e) = reload_config(app.handle()) {
+                eprintln!("Failed to load config, using defaults: {}", e);
+            }
+
+            if let Err(e) = restore_session_windows(app.handle()) {
+                eprintln!("Failed to restore session windows: {}", e);
+            }
+            if let Err(e) = restore_session_roots(app.handle()) {
+                eprintln!("Failed to restore session workspaces: {}", e);
+            }
+
+            // Automation API for scripts and other tools (`velt rpc`)
+            #[cfg(unix)]
+            rpc::start_server(app.handle().clone());
 
             // Intercept Shift+Tab (ISO_Left_Tab) at GTK level.
             // On Linux/GTK, Shift+Tab generates a different keyval (ISO_Left_Tab = 0xfe20)
@@ -661,6 +1639,14 @@ pub fn run_with_files(files: Vec<String>) {
             }
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // File state changes are saved in batches: write out the last ones
+            if let tauri::RunEvent::Exit = event {
+                if let Err(e) = app.state::<FileStateDbState>().0.flush() {
+                    eprintln!("Failed to save file state: {}", e);
+                }
+            }
+        });
 }
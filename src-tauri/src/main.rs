@@ -1,35 +1,112 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use clap::{Parser, Subcommand};
+use clap::Parser;
 use std::env;
+use std::path::Path;
+use velt_lib::cli::{Cli, Commands, ConfigCommands, SessionCommands, ThemeCommands};
+use velt_lib::config;
+use velt_lib::convert::{self, Conversion, Outcome};
 
-#[derive(Parser)]
-#[command(name = "velt")]
-#[command(version = env!("CARGO_PKG_VERSION"))]
-#[command(about = "A modern, minimal text editor", long_about = None)]
-struct Cli {
-    /// File(s) to open
-    #[arg(value_name = "FILE")]
-    files: Vec<String>,
+fn main() {
+    let cli = Cli::parse();
 
-    #[command(subcommand)]
-    command: Option<Commands>,
-}
+    match cli.command {
+        Some(Commands::Version) => {
+            println!("Velt {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Some(Commands::Session { action }) => {
+            if let Err(e) = run_session_command(action) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Config { action }) => {
+            if let Err(e) = run_config_command(action) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Convert {
+            to,
+            bom,
+            eol,
+            from,
+            dry_run,
+            check,
+            files,
+        }) => {
+            if bom && to != Some(velt_lib::cli::EncodingArg::Utf8) {
+                eprintln!("Error: --bom only applies to --to utf-8");
+                std::process::exit(2);
+            }
+            let conversion = Conversion {
+                to: to.map(|to| to.label(bom).to_string()),
+                from: from.map(|from| from.label(false).to_string()),
+                eol,
+            };
+            let ok = run_convert_command(&conversion, &files, dry_run, check);
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        Some(Commands::Doctor) => {
+            let healthy = velt_lib::doctor::run();
+            std::process::exit(if healthy { 0 } else { 1 });
+        }
+        Some(Commands::Theme { action }) => {
+            if let Err(e) = run_theme_command(action) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Rpc { method, params }) => {
+            if let Err(e) = run_rpc_command(cli.session.as_deref(), &method, params.as_deref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Show version information
-    Version,
-}
+    // Links come from other programs: refuse malformed ones up front
+    if let Err(e) = cli.check_urls() {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
 
-fn main() {
-    let cli = Cli::parse();
+    if let Err(e) = velt_lib::session::set_active_session(cli.session.as_deref()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
+
+    // Read stdin and block until the files are closed in this process;
+    // a second one does the opening
+    if cli.needs_relaunch() {
+        std::process::exit(velt_lib::cli::relaunch(&cli));
+    }
+
+    if let Err(e) = config::set_cli_overrides(&cli.overrides) {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
 
-    // Handle version command
-    if let Some(Commands::Version) = cli.command {
-        println!("Velt {}", env!("CARGO_PKG_VERSION"));
-        return;
+    // Layer the settings of the enclosing workspace, if any, over the user config.
+    // A named session reopens its own workspace wherever it is started from.
+    let session_root = cli
+        .session
+        .as_ref()
+        .and_then(|_| velt_lib::session::load_session().ok())
+        .and_then(|session| session.roots.into_iter().next())
+        .map(std::path::PathBuf::from)
+        .and_then(|root| config::find_workspace_root(&root));
+    if session_root.is_some() {
+        config::set_workspace_root(session_root);
+    } else if let Ok(cwd) = env::current_dir() {
+        config::set_workspace_root(config::find_workspace_root(&cwd));
     }
 
     // Set environment variables for Wayland compatibility
@@ -45,15 +122,292 @@ fn main() {
         }
     }
 
-    // Filter to keep only valid file paths (not directories)
-    let file_paths: Vec<String> = cli
-        .files
-        .into_iter()
-        .filter(|path| {
-            let p = std::path::Path::new(path);
-            p.is_file() || !p.exists() // Allow non-existent files (new files)
-        })
-        .collect();
+    let cwd = env::current_dir().unwrap_or_default();
+    let files = cli.resolve_files(&cwd);
+
+    velt_lib::run_with_files(files, cli.open_options());
+}
+
+fn run_rpc_command(
+    session: Option<&str>,
+    method: &str,
+    params: Option<&str>,
+) -> Result<(), String> {
+    velt_lib::session::set_active_session(session)?;
+    let params = match params {
+        Some(params) => {
+            serde_json::from_str(params).map_err(|e| format!("Invalid params: {}", e))?
+        }
+        None => serde_json::json!({}),
+    };
+
+    #[cfg(unix)]
+    {
+        let result = velt_lib::rpc::call(method, params)?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?
+        );
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (method, params);
+        Err("The automation API is only available on Unix".to_string())
+    }
+}
+
+fn run_session_command(action: SessionCommands) -> Result<(), String> {
+    match action {
+        SessionCommands::List => {
+            let sessions = velt_lib::session::list_sessions()?;
+            if sessions.is_empty() {
+                println!("No named sessions");
+            }
+            for session in sessions {
+                println!(
+                    "{}\t{} window(s), {} tab(s)\t{}",
+                    session.name,
+                    session.window_count,
+                    session.tab_count,
+                    session.roots.join(", ")
+                );
+            }
+        }
+        SessionCommands::Delete { name } => {
+            velt_lib::session::delete_session(&name)?;
+            println!("Deleted session '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+fn run_config_command(action: ConfigCommands) -> Result<(), String> {
+    if let Some(recovery) = config::recover_user_config()? {
+        eprintln!("{}", recovery.describe());
+    }
+    config::initialize_config()?;
+    // Resolve settings as an editor started here would
+    if let Ok(cwd) = env::current_dir() {
+        config::set_workspace_root(config::find_workspace_root(&cwd));
+    }
+
+    match action {
+        ConfigCommands::Get { key: Some(key) } => match config::get_config_value(&key)? {
+            serde_json::Value::String(value) => println!("{}", value),
+            value => println!("{}", value),
+        },
+        ConfigCommands::Get { key: None } => {
+            let config = config::load_config()?;
+            let json = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+        ConfigCommands::Set { key, value } => {
+            config::set_config_value(&key, config::parse_config_value(&value))?;
+        }
+        ConfigCommands::Unset { key } => {
+            if !config::unset_config_value(&key)? {
+                println!("'{}' is not set in the user config", key);
+            }
+        }
+        ConfigCommands::Path => {
+            println!("{}", config::get_user_config_path()?.display());
+        }
+        ConfigCommands::Edit => {
+            let path = config::get_user_config_path()?;
+            edit_file(&path)?;
+            let problems = config::config_problems()
+                .map_err(|e| format!("The config is now invalid: {}", e))?;
+            if !problems.is_empty() {
+                return Err(format!(
+                    "The config is now invalid: {}",
+                    problems.join("; ")
+                ));
+            }
+        }
+        ConfigCommands::Reset { yes } => {
+            let path = config::get_user_config_path()?;
+            if !yes && !confirm(&format!("Reset {} to the defaults?", path.display()))? {
+                return Ok(());
+            }
+            if let Some(backup) = config::reset_user_config()? {
+                println!("Previous config saved to {}", backup.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Open a file in the user's editor ($VISUAL, $EDITOR, or Velt itself) and wait for it
+fn edit_file(path: &Path) -> Result<(), String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty());
+
+    let mut command = match editor {
+        Some(editor) => {
+            let mut words = editor.split_whitespace();
+            let mut command = std::process::Command::new(words.next().unwrap_or_default());
+            command.args(words);
+            command
+        }
+        None => {
+            let exe = env::current_exe().map_err(|e| e.to_string())?;
+            let mut command = std::process::Command::new(exe);
+            command.arg("--wait");
+            command
+        }
+    };
+
+    let status = command
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Failed to start the editor: {}", e))?;
+    if !status.success() {
+        return Err(format!("The editor exited with {}", status));
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool, String> {
+    use std::io::Write;
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| e.to_string())?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Convert files, or only report what would change. Returns false on errors,
+/// and in check mode when a file needs converting.
+fn run_convert_command(
+    conversion: &Conversion,
+    files: &[std::path::PathBuf],
+    dry_run: bool,
+    check: bool,
+) -> bool {
+    let write = !dry_run && !check;
+    let (mut converted, mut unchanged, mut failed) = (0, 0, 0);
+
+    for file in files {
+        match convert::convert_file(file, conversion, write) {
+            Ok(Outcome::Unchanged) => unchanged += 1,
+            Ok(Outcome::Converted(change)) => {
+                converted += 1;
+                let verb = if write { "converted" } else { "would convert" };
+                println!("{}: {} ({})", file.display(), verb, change);
+            }
+            Ok(Outcome::Skipped(reason)) => println!("{}: skipped ({})", file.display(), reason),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", file.display(), e);
+            }
+        }
+    }
+
+    let verb = if write { "converted" } else { "to convert" };
+    println!(
+        "{} file(s) {}, {} unchanged, {} failed",
+        converted, verb, unchanged, failed
+    );
+    failed == 0 && !(check && converted > 0)
+}
+
+fn run_theme_command(action: ThemeCommands) -> Result<(), String> {
+    config::initialize_config()?;
+
+    match action {
+        ThemeCommands::List => {
+            let config = config::load_config()?;
+            let mut themes = config::list_themes(&config)?;
+            themes.retain(|name| name != "current");
+            themes.sort();
+            for name in themes {
+                let marker = if name == config.theme { '*' } else { ' ' };
+                println!("{} {}", marker, name);
+            }
+        }
+        ThemeCommands::Show { name } => {
+            let theme = config::load_theme(&name, &config::load_config()?)?;
+            println!("{}", theme_json(&theme)?);
+        }
+        ThemeCommands::Validate { files } => {
+            let mut invalid = 0;
+            for file in &files {
+                match read_theme_file(file) {
+                    Ok(theme) => println!("{}: ok ({})", file.display(), theme.name),
+                    Err(e) => {
+                        invalid += 1;
+                        eprintln!("{}: {}", file.display(), e);
+                    }
+                }
+            }
+            if invalid > 0 {
+                return Err(format!(
+                    "{} of {} theme file(s) invalid",
+                    invalid,
+                    files.len()
+                ));
+            }
+        }
+        ThemeCommands::Export { name, output } => {
+            let theme = config::load_theme(&name, &config::load_config()?)?;
+            let json = theme_json(&theme)?;
+            match output {
+                Some(path) => std::fs::write(&path, json + "\n")
+                    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?,
+                None => println!("{}", json),
+            }
+        }
+        ThemeCommands::Import { file, name, force } => {
+            let theme = read_theme_file(&file)?;
+            let name = match name {
+                Some(name) => name,
+                None => file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            };
+            config::validate_theme_name(&name)?;
+
+            let config = config::load_config()?;
+            if !force && config::list_themes(&config)?.contains(&name) {
+                return Err(format!(
+                    "Theme '{}' already exists (use --force to replace it)",
+                    name
+                ));
+            }
+            config::save_custom_theme(&name, &theme)?;
+            println!("Imported theme '{}'", name);
+        }
+        ThemeCommands::Set { name, startup } => {
+            let mut config = config::load_config()?;
+            let theme = config::load_theme(&name, &config)?;
+            config::save_current_theme(&theme)?;
+            if startup {
+                config.theme = name.clone();
+                config::save_config(&config)?;
+            }
+            println!("Applied theme '{}'", name);
+        }
+        ThemeCommands::Delete { name } => {
+            config::delete_theme(&name)?;
+            println!("Deleted theme '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+fn read_theme_file(path: &Path) -> Result<config::Theme, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    config::parse_theme(&content)
+}
 
-    velt_lib::run_with_files(file_paths);
+fn theme_json(theme: &config::Theme) -> Result<String, String> {
+    serde_json::to_string_pretty(theme).map_err(|e| e.to_string())
 }
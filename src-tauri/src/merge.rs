@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+use std::ops::Range;
+
+/// A region of the merged text that could not be resolved automatically
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConflictRegion {
+    /// First line of the region in the merged text (0-based, on the `<<<<<<<` marker)
+    #[serde(rename = "startLine")]
+    pub start_line: usize,
+    /// Last line of the region in the merged text (0-based, on the `>>>>>>>` marker)
+    #[serde(rename = "endLine")]
+    pub end_line: usize,
+    pub base: String,
+    /// Text from the editor buffer
+    pub ours: String,
+    /// Text from the file on disk
+    pub theirs: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MergeResult {
+    pub merged: String,
+    pub conflicts: Vec<ConflictRegion>,
+}
+
+/// A contiguous change of one side against the base, in line indices
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+const MARKER_OURS: &str = "<<<<<<< buffer\n";
+const MARKER_SEPARATOR: &str = "=======\n";
+const MARKER_THEIRS: &str = ">>>>>>> disk\n";
+
+/// Three-way merge of `ours` (editor buffer) and `theirs` (disk) against their common `base`.
+///
+/// Non-overlapping changes are applied automatically. Overlapping changes that differ
+/// are written with git-style conflict markers and reported as conflict regions.
+pub fn merge(base: &str, ours: &str, theirs: &str) -> MergeResult {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let ours_hunks = diff_hunks(&base_lines, &ours_lines);
+    let theirs_hunks = diff_hunks(&base_lines, &theirs_lines);
+
+    let mut merged = String::new();
+    let mut conflicts = Vec::new();
+    let mut merged_line = 0;
+
+    // Base position up to which output has been written, and the per-side line offsets
+    // (side index - base index) accumulated from hunks already consumed
+    let mut base_pos = 0;
+    let mut ours_offset: isize = 0;
+    let mut theirs_offset: isize = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        // Start a chunk with whichever hunk comes first in the base
+        let take_ours = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(o), Some(t)) => o.base.start <= t.base.start,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        let first = if take_ours {
+            &ours_hunks[i]
+        } else {
+            &theirs_hunks[j]
+        };
+        let start = first.base.start;
+        let mut end = first.base.end;
+        let (ours_from, theirs_from) = (i, j);
+
+        // Grow the chunk while a hunk from either side touches it
+        loop {
+            if let Some(h) = ours_hunks.get(i).filter(|h| h.base.start <= end) {
+                end = end.max(h.base.end);
+                i += 1;
+            } else if let Some(h) = theirs_hunks.get(j).filter(|h| h.base.start <= end) {
+                end = end.max(h.base.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        // Unchanged lines before the chunk
+        for line in &base_lines[base_pos..start] {
+            merged.push_str(line);
+            merged_line += 1;
+        }
+
+        let ours_text = side_text(
+            &ours_lines,
+            &ours_hunks[ours_from..i],
+            start,
+            end,
+            &mut ours_offset,
+        );
+        let theirs_text = side_text(
+            &theirs_lines,
+            &theirs_hunks[theirs_from..j],
+            start,
+            end,
+            &mut theirs_offset,
+        );
+
+        let ours_changed = i > ours_from;
+        let theirs_changed = j > theirs_from;
+
+        if !theirs_changed || (ours_changed && ours_text == theirs_text) {
+            merged_line += count_lines(&ours_text);
+            merged.push_str(&ours_text);
+        } else if !ours_changed {
+            merged_line += count_lines(&theirs_text);
+            merged.push_str(&theirs_text);
+        } else {
+            let base_text = base_lines[start..end].concat();
+            let start_line = merged_line;
+
+            ensure_line_break(&mut merged);
+            merged.push_str(MARKER_OURS);
+            merged.push_str(&ours_text);
+            ensure_line_break(&mut merged);
+            merged.push_str(MARKER_SEPARATOR);
+            merged.push_str(&theirs_text);
+            ensure_line_break(&mut merged);
+            merged.push_str(MARKER_THEIRS);
+
+            merged_line += 3 + count_lines(&ours_text) + count_lines(&theirs_text);
+            conflicts.push(ConflictRegion {
+                start_line,
+                end_line: merged_line - 1,
+                base: base_text,
+                ours: ours_text,
+                theirs: theirs_text,
+            });
+        }
+
+        base_pos = end;
+    }
+
+    for line in &base_lines[base_pos..] {
+        merged.push_str(line);
+    }
+
+    MergeResult { merged, conflicts }
+}
+
+/// Collect the changed line ranges of `side` against `base`, merging adjacent operations
+fn diff_hunks(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+
+    for op in capture_diff_slices(Algorithm::Myers, base, side) {
+        let (tag, base_range, side_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some(last)
+                if last.base.end == base_range.start && last.side.end == side_range.start =>
+            {
+                last.base.end = base_range.end;
+                last.side.end = side_range.end;
+            }
+            _ => hunks.push(Hunk {
+                base: base_range,
+                side: side_range,
+            }),
+        }
+    }
+
+    hunks
+}
+
+/// Text of one side covering base lines `start..end`, given the side's hunks inside that range
+fn side_text(
+    lines: &[&str],
+    hunks: &[Hunk],
+    start: usize,
+    end: usize,
+    offset: &mut isize,
+) -> String {
+    let side_start = (start as isize + *offset) as usize;
+    for hunk in hunks {
+        *offset += hunk.side.len() as isize - hunk.base.len() as isize;
+    }
+    let side_end = (end as isize + *offset) as usize;
+    lines[side_start..side_end].concat()
+}
+
+fn count_lines(text: &str) -> usize {
+    text.split_inclusive('\n').count()
+}
+
+fn ensure_line_break(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
@@ -0,0 +1,351 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor};
+
+/// Languages with a tree-sitter grammar for symbol extraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutlineLanguage {
+    Rust,
+    Python,
+    TypeScript,
+    Tsx,
+    Json,
+    Yaml,
+    Markdown,
+}
+
+/// Position range of a symbol (1-based lines and character columns, like the status bar)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolRange {
+    #[serde(rename = "startLine")]
+    pub start_line: usize,
+    #[serde(rename = "startColumn")]
+    pub start_column: usize,
+    #[serde(rename = "endLine")]
+    pub end_line: usize,
+    #[serde(rename = "endColumn")]
+    pub end_column: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DocumentSymbol {
+    pub name: String,
+    /// e.g. `function`, `struct`, `class`, `heading`, `key`
+    pub kind: String,
+    /// Range of the whole definition
+    pub range: SymbolRange,
+    /// Range of the symbol's name, where the cursor goes on "go to symbol"
+    #[serde(rename = "selectionRange")]
+    pub selection_range: SymbolRange,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<DocumentSymbol>,
+}
+
+// Queries capture the symbol name as `@name` and the definition as `@definition.<kind>`
+const RUST_QUERY: &str = r#"
+(mod_item name: (identifier) @name) @definition.module
+(struct_item name: (type_identifier) @name) @definition.struct
+(union_item name: (type_identifier) @name) @definition.struct
+(enum_item name: (type_identifier) @name) @definition.enum
+(enum_variant name: (identifier) @name) @definition.variant
+(trait_item name: (type_identifier) @name) @definition.trait
+(impl_item type: (_) @name) @definition.impl
+(function_item name: (identifier) @name) @definition.function
+(function_signature_item name: (identifier) @name) @definition.function
+(const_item name: (identifier) @name) @definition.constant
+(static_item name: (identifier) @name) @definition.constant
+(type_item name: (type_identifier) @name) @definition.type
+(macro_definition name: (identifier) @name) @definition.macro
+(field_declaration name: (field_identifier) @name) @definition.field
+"#;
+
+const PYTHON_QUERY: &str = r#"
+(class_definition name: (identifier) @name) @definition.class
+(function_definition name: (identifier) @name) @definition.function
+"#;
+
+const TYPESCRIPT_QUERY: &str = r#"
+(class_declaration name: (_) @name) @definition.class
+(abstract_class_declaration name: (_) @name) @definition.class
+(interface_declaration name: (_) @name) @definition.interface
+(enum_declaration name: (_) @name) @definition.enum
+(type_alias_declaration name: (_) @name) @definition.type
+(internal_module name: (_) @name) @definition.module
+(function_declaration name: (_) @name) @definition.function
+(generator_function_declaration name: (_) @name) @definition.function
+(method_definition name: (_) @name) @definition.method
+(method_signature name: (_) @name) @definition.method
+(public_field_definition name: (_) @name) @definition.property
+(property_signature name: (_) @name) @definition.property
+(variable_declarator
+  name: (identifier) @name
+  value: [(arrow_function) (function_expression)]) @definition.function
+"#;
+
+const JSON_QUERY: &str = r#"
+(pair key: (string (string_content) @name)) @definition.key
+"#;
+
+const YAML_QUERY: &str = r#"
+(block_mapping_pair key: (_) @name) @definition.key
+(flow_pair key: (_) @name) @definition.key
+"#;
+
+// Headings are ranged by level afterwards, see `range_headings`
+const MARKDOWN_QUERY: &str = r#"
+(atx_heading heading_content: (_) @name) @definition.heading
+(setext_heading heading_content: (_) @name) @definition.heading
+"#;
+
+impl OutlineLanguage {
+    /// Resolve a language name as used by the frontend (e.g. `rust`, `typescript`, `markdown`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "rust" | "rs" => Some(Self::Rust),
+            "python" | "py" => Some(Self::Python),
+            "typescript" | "ts" | "javascript" | "js" => Some(Self::TypeScript),
+            "tsx" | "jsx" => Some(Self::Tsx),
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "markdown" | "md" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    /// Guess the language from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "rs" => Some(Self::Rust),
+            "py" | "pyi" => Some(Self::Python),
+            "ts" | "mts" | "cts" | "js" | "mjs" | "cjs" => Some(Self::TypeScript),
+            "tsx" | "jsx" => Some(Self::Tsx),
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "md" | "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    fn grammar(self) -> Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+            Self::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Self::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Self::Json => tree_sitter_json::LANGUAGE.into(),
+            Self::Yaml => tree_sitter_yaml::LANGUAGE.into(),
+            Self::Markdown => tree_sitter_md::LANGUAGE.into(),
+        }
+    }
+
+    /// Compiled symbol query, built once per language
+    fn query(self) -> Result<&'static Query, String> {
+        static QUERIES: [OnceLock<Result<Query, String>>; 7] = [const { OnceLock::new() }; 7];
+
+        let (index, source) = match self {
+            Self::Rust => (0, RUST_QUERY),
+            Self::Python => (1, PYTHON_QUERY),
+            Self::TypeScript => (2, TYPESCRIPT_QUERY),
+            Self::Tsx => (3, TYPESCRIPT_QUERY),
+            Self::Json => (4, JSON_QUERY),
+            Self::Yaml => (5, YAML_QUERY),
+            Self::Markdown => (6, MARKDOWN_QUERY),
+        };
+
+        QUERIES[index]
+            .get_or_init(|| Query::new(&self.grammar(), source).map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(|e| format!("Invalid symbol query for {:?}: {}", self, e))
+    }
+}
+
+/// Extract all symbols of a document as a flat list sorted by position.
+///
+/// Each symbol is returned together with the byte range of its definition,
+/// which `document_outline` uses to nest them.
+pub fn extract_symbols(
+    source: &str,
+    language: OutlineLanguage,
+) -> Result<Vec<(DocumentSymbol, std::ops::Range<usize>)>, String> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(&language.grammar())
+        .map_err(|e| e.to_string())?;
+    let tree = parser
+        .parse(source, None)
+        .ok_or("Failed to parse document")?;
+
+    let query = language.query()?;
+    let name_index = query
+        .capture_index_for_name("name")
+        .ok_or("Symbol query has no @name capture")?;
+
+    let mut symbols: Vec<(DocumentSymbol, std::ops::Range<usize>)> = Vec::new();
+    // Heading levels by start byte
+    let mut levels = HashMap::new();
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(query, tree.root_node(), source.as_bytes());
+
+    while let Some(m) = matches.next() {
+        let mut name_node = None;
+        let mut definition = None;
+
+        for capture in m.captures {
+            if capture.index == name_index {
+                name_node = Some(capture.node);
+            } else if let Some(kind) =
+                query.capture_names()[capture.index as usize].strip_prefix("definition.")
+            {
+                definition = Some((capture.node, kind));
+            }
+        }
+
+        let (Some(name_node), Some((node, kind))) = (name_node, definition) else {
+            continue;
+        };
+
+        let name = symbol_name(&source[name_node.byte_range()]);
+        if name.is_empty() {
+            continue;
+        }
+        if let Some(level) = heading_level(node) {
+            levels.insert(node.start_byte(), level);
+        }
+
+        symbols.push((
+            DocumentSymbol {
+                name,
+                kind: kind.to_string(),
+                range: node_range(source, node),
+                selection_range: node_range(source, name_node),
+                children: Vec::new(),
+            },
+            node.byte_range(),
+        ));
+    }
+
+    symbols.sort_by(|(_, a), (_, b)| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+    symbols.dedup_by(|(a, a_range), (b, b_range)| a_range == b_range && a.kind == b.kind);
+    if !levels.is_empty() {
+        range_headings(source, &mut symbols, &levels);
+    }
+
+    Ok(symbols)
+}
+
+/// Level of a Markdown heading node: 1-6 for `#` headings, 1 for `===` and 2
+/// for `---` underlines
+fn heading_level(node: Node) -> Option<usize> {
+    let mut cursor = node.walk();
+    let level = node
+        .children(&mut cursor)
+        .find_map(|child| match child.kind() {
+            "setext_h1_underline" => Some(1),
+            "setext_h2_underline" => Some(2),
+            kind => kind
+                .strip_prefix("atx_h")
+                .and_then(|kind| kind.strip_suffix("_marker"))
+                .and_then(|level| level.parse().ok()),
+        });
+    level
+}
+
+/// Extend each heading up to the next heading of the same or a higher level,
+/// so that `document_outline` nests the headings below it. The grammar only
+/// builds such sections for `#` headings, not for underlined ones.
+fn range_headings(
+    source: &str,
+    symbols: &mut [(DocumentSymbol, std::ops::Range<usize>)],
+    levels: &HashMap<usize, usize>,
+) {
+    for i in 0..symbols.len() {
+        let Some(&level) = levels.get(&symbols[i].1.start) else {
+            continue;
+        };
+        let end = symbols[i + 1..]
+            .iter()
+            .find(|(_, range)| levels.get(&range.start).is_some_and(|l| *l <= level))
+            .map_or(source.len(), |(_, range)| range.start);
+
+        let (line, column) = byte_position(source, end);
+        let (symbol, range) = &mut symbols[i];
+        range.end = end;
+        symbol.range.end_line = line;
+        symbol.range.end_column = column;
+    }
+}
+
+/// Build the hierarchical outline of a document: symbols nested by containment
+pub fn document_outline(
+    source: &str,
+    language: OutlineLanguage,
+) -> Result<Vec<DocumentSymbol>, String> {
+    let symbols = extract_symbols(source, language)?;
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<(DocumentSymbol, usize)> = Vec::new();
+
+    for (symbol, range) in symbols {
+        while stack.last().is_some_and(|(_, end)| range.start >= *end) {
+            close_symbol(&mut stack, &mut roots);
+        }
+        stack.push((symbol, range.end));
+    }
+    while !stack.is_empty() {
+        close_symbol(&mut stack, &mut roots);
+    }
+
+    Ok(roots)
+}
+
+/// Pop the innermost open symbol and attach it to its parent (or the roots)
+fn close_symbol(stack: &mut Vec<(DocumentSymbol, usize)>, roots: &mut Vec<DocumentSymbol>) {
+    if let Some((symbol, _)) = stack.pop() {
+        match stack.last_mut() {
+            Some((parent, _)) => parent.children.push(symbol),
+            None => roots.push(symbol),
+        }
+    }
+}
+
+/// Normalise the source text of a name node (quoted keys, multi-line headings)
+fn symbol_name(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let unquoted = text
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .or_else(|| text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')));
+    unquoted.unwrap_or(&text).to_string()
+}
+
+fn node_range(source: &str, node: Node) -> SymbolRange {
+    let start = node.start_position();
+    let end = node.end_position();
+    SymbolRange {
+        start_line: start.row + 1,
+        start_column: char_column(source, node.start_byte(), start.column) + 1,
+        end_line: end.row + 1,
+        end_column: char_column(source, node.end_byte(), end.column) + 1,
+    }
+}
+
+/// 1-based line and character column of a byte offset
+fn byte_position(source: &str, byte: usize) -> (usize, usize) {
+    let before = &source[..byte];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Convert a byte column (as reported by tree-sitter) into a character column
+fn char_column(source: &str, byte: usize, byte_column: usize) -> usize {
+    source
+        .get(byte - byte_column..byte)
+        .map_or(byte_column, |line| line.chars().count())
+}
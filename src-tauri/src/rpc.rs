@@ -0,0 +1,584 @@
+// Only served on Unix sockets for now
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use crate::{cli, config, session};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Version of the automation API, bumped on incompatible changes to its
+/// methods, parameters or results
+pub const API_VERSION: u32 = 1;
+
+/// How long a window gets to answer a request before it fails
+const FRONTEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often `file.open` looks for the tab of the file it opened
+const OPEN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The request was valid but could not be carried out (unknown tab, failed save, ...)
+const REQUEST_FAILED: i64 = -32000;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Requests passed on to a window, waiting for its frontend to answer
+#[derive(Default)]
+pub struct PendingRequests {
+    next_id: AtomicU64,
+    senders: Mutex<HashMap<u64, mpsc::Sender<Result<Value, String>>>>,
+}
+
+impl PendingRequests {
+    /// Hand a window's answer to the request waiting for it
+    pub fn respond(&self, id: u64, result: Result<Value, String>) -> Result<(), String> {
+        let sender = self
+            .senders
+            .lock()
+            .map_err(|e| e.to_string())?
+            .remove(&id)
+            .ok_or_else(|| format!("No pending request {}", id))?;
+        // The request may have timed out in the meantime
+        let _ = sender.send(result);
+        Ok(())
+    }
+}
+
+/// A tab as the API reports it. Windows keep the backend up to date with
+/// their tabs, so that tabs are listed and found without asking the windows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabInfo {
+    #[serde(rename = "tabId")]
+    pub tab_id: String,
+    pub path: Option<String>,
+    pub dirty: bool,
+    pub active: bool,
+    pub encoding: Option<String>,
+    pub language: Option<String>,
+    #[serde(rename = "readOnly")]
+    pub read_only: bool,
+}
+
+/// Tabs of each window, keyed by window label, as the windows last reported them
+#[derive(Default)]
+pub struct WindowTabs(Mutex<HashMap<String, Vec<TabInfo>>>);
+
+impl WindowTabs {
+    pub fn set(&self, label: &str, tabs: Vec<TabInfo>) -> Result<(), String> {
+        self.0
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(label.to_string(), tabs);
+        Ok(())
+    }
+
+    pub fn remove(&self, label: &str) {
+        if let Ok(mut windows) = self.0.lock() {
+            windows.remove(label);
+        }
+    }
+
+    fn get(&self, label: &str) -> Vec<TabInfo> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|windows| windows.get(label).cloned())
+            .unwrap_or_default()
+    }
+
+    /// The window holding a tab, and the tab
+    fn find(&self, tab_id: &str) -> Option<(String, TabInfo)> {
+        let windows = self.0.lock().ok()?;
+        windows.iter().find_map(|(label, tabs)| {
+            let tab = tabs.iter().find(|tab| tab.tab_id == tab_id)?;
+            Some((label.clone(), tab.clone()))
+        })
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct FrontendRequest {
+    id: u64,
+    method: String,
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct WindowParams {
+    window: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenParams {
+    path: String,
+    line: Option<u32>,
+    column: Option<u32>,
+    window: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TabParams {
+    #[serde(rename = "tabId")]
+    tab_id: Option<String>,
+    window: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReplaceParams {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct InsertParams {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct SaveParams {
+    path: Option<String>,
+}
+
+/// Socket the API is served on: one per user and session, in the runtime
+/// directory where there is one and in the state directory otherwise
+pub fn socket_path() -> Result<PathBuf, String> {
+    let dir = match dirs::runtime_dir() {
+        Some(dir) => dir.join("velt"),
+        None => config::get_state_dir()?,
+    };
+    let name = match session::get_active_session() {
+        Some(name) => format!("rpc-{}.sock", name),
+        None => "rpc.sock".to_string(),
+    };
+    Ok(dir.join(name))
+}
+
+/// Serve the API in the background for as long as the app runs
+#[cfg(unix)]
+pub fn start_server(app: AppHandle) {
+    let path = match socket_path() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Failed to start RPC server: {}", e);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        if let Err(e) = serve(&app, &path) {
+            eprintln!("Failed to start RPC server: {}", e);
+        }
+    });
+}
+
+#[cfg(unix)]
+fn serve(app: &AppHandle, path: &Path) -> Result<(), String> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+    }
+
+    // A socket nobody answers on is left over from a crashed instance
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("{} is in use by another instance", path.display()));
+        }
+        fs::remove_file(path).map_err(|e| e.to_string())?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| e.to_string())?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let app = app.clone();
+        std::thread::spawn(move || handle_connection(&app, stream));
+    }
+    Ok(())
+}
+
+/// Answer newline-delimited requests until the client disconnects
+#[cfg(unix)]
+fn handle_connection(app: &AppHandle, stream: std::os::unix::net::UnixStream) {
+    use std::io::{BufRead, BufReader, Write};
+
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut writer = stream;
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_message(app, &line) {
+            if writeln!(writer, "{}", response).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Handle one JSON-RPC message. Notifications (requests without an id) get
+/// no response.
+fn handle_message(app: &AppHandle, line: &str) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+    };
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str);
+
+    let result = match method {
+        _ if request.is_array() => Err(RpcError::new(
+            INVALID_REQUEST,
+            "Batch requests are not supported",
+        )),
+        _ if request.get("jsonrpc") != Some(&json!("2.0")) => Err(RpcError::new(
+            INVALID_REQUEST,
+            "Expected \"jsonrpc\": \"2.0\"",
+        )),
+        None => Err(RpcError::new(INVALID_REQUEST, "Missing method")),
+        Some(method) => {
+            let params = request.get("params").cloned().unwrap_or(json!({}));
+            dispatch(app, method, params)
+        }
+    };
+
+    let id = match id {
+        Some(id) => id,
+        // Invalid requests are answered even without an id
+        None if request.is_array() || method.is_none() => Value::Null,
+        None => return None,
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_response(id, e.code, e.message),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: &Value) -> Result<T, RpcError> {
+    serde_json::from_value(params.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn dispatch(app: &AppHandle, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "velt.version" => Ok(json!({
+            "apiVersion": API_VERSION,
+            "version": env!("CARGO_PKG_VERSION"),
+        })),
+        "windows.list" => Ok(list_windows(app)),
+        "tabs.list" => {
+            let p: WindowParams = parse_params(&params)?;
+            list_tabs(app, p.window)
+        }
+        "file.open" => {
+            let p: OpenParams = parse_params(&params)?;
+            open_file(app, p)
+        }
+        "buffer.read" => {
+            let (label, tab) = find_tab(app, parse_params(&params)?)?;
+            call_window(app, &label, "buffer.read", json!({ "tabId": tab.tab_id }))
+                .map_err(|e| RpcError::new(REQUEST_FAILED, e))
+        }
+        "buffer.replace" => {
+            let p: ReplaceParams = parse_params(&params)?;
+            let (label, tab) = find_writable_tab(app, parse_params(&params)?)?;
+            let params = json!({ "tabId": tab.tab_id, "content": p.content });
+            call_window(app, &label, "buffer.replace", params)
+                .map_err(|e| RpcError::new(REQUEST_FAILED, e))
+        }
+        "buffer.insert" => {
+            let p: InsertParams = parse_params(&params)?;
+            let (label, tab) = find_writable_tab(app, parse_params(&params)?)?;
+            let params = json!({ "tabId": tab.tab_id, "text": p.text });
+            call_window(app, &label, "buffer.insert", params)
+                .map_err(|e| RpcError::new(REQUEST_FAILED, e))
+        }
+        "file.save" => {
+            let p: SaveParams = parse_params(&params)?;
+            let (label, tab) = find_writable_tab(app, parse_params(&params)?)?;
+            save_tab(app, &label, tab, p.path)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method '{}'", method),
+        )),
+    }
+}
+
+fn window_labels(app: &AppHandle) -> Vec<String> {
+    let mut labels: Vec<String> = app.webview_windows().into_keys().collect();
+    labels.sort();
+    labels
+}
+
+fn list_windows(app: &AppHandle) -> Value {
+    let windows = app.webview_windows();
+    let list: Vec<Value> = window_labels(app)
+        .into_iter()
+        .filter_map(|label| {
+            let window = windows.get(&label)?;
+            Some(json!({
+                "label": label,
+                "title": window.title().unwrap_or_default(),
+                "focused": window.is_focused().unwrap_or(false),
+            }))
+        })
+        .collect();
+    Value::Array(list)
+}
+
+fn list_tabs(app: &AppHandle, window: Option<String>) -> Result<Value, RpcError> {
+    let labels = match window {
+        Some(label) if app.get_webview_window(&label).is_none() => {
+            return Err(RpcError::new(
+                REQUEST_FAILED,
+                format!("Unknown window '{}'", label),
+            ))
+        }
+        Some(label) => vec![label],
+        None => window_labels(app),
+    };
+
+    let windows = app.state::<WindowTabs>();
+    let mut list = Vec::new();
+    for label in labels {
+        for tab in windows.get(&label) {
+            let mut tab = serde_json::to_value(tab)
+                .map_err(|e| RpcError::new(REQUEST_FAILED, e.to_string()))?;
+            tab["window"] = json!(label);
+            list.push(tab);
+        }
+    }
+    Ok(Value::Array(list))
+}
+
+/// The window and tab a request is for. Without a tab id, the active tab of
+/// the given window, or else of the focused one.
+fn find_tab(app: &AppHandle, p: TabParams) -> Result<(String, TabInfo), RpcError> {
+    let windows = app.state::<WindowTabs>();
+    let found = match (&p.tab_id, p.window) {
+        (Some(id), window) => windows
+            .find(id)
+            .filter(|(label, _)| window.as_ref().is_none_or(|window| window == label)),
+        (None, window) => window
+            .or_else(|| crate::target_window(app).map(|window| window.label().to_string()))
+            .and_then(|label| {
+                let tab = windows.get(&label).into_iter().find(|tab| tab.active)?;
+                Some((label, tab))
+            }),
+    };
+
+    found.ok_or_else(|| {
+        RpcError::new(
+            REQUEST_FAILED,
+            match p.tab_id {
+                Some(id) => format!("Unknown tab '{}'", id),
+                None => "No active tab".to_string(),
+            },
+        )
+    })
+}
+
+/// Like `find_tab`, refusing tabs opened read-only (`velt --readonly`)
+fn find_writable_tab(app: &AppHandle, p: TabParams) -> Result<(String, TabInfo), RpcError> {
+    let (label, tab) = find_tab(app, p)?;
+    if tab.read_only {
+        return Err(RpcError::new(REQUEST_FAILED, "Tab is read-only"));
+    }
+    Ok((label, tab))
+}
+
+/// Save a tab's text, to its file or to `path`. The text is the only part
+/// that lives in the window; it is written here, like the editor's saves.
+fn save_tab(
+    app: &AppHandle,
+    label: &str,
+    tab: TabInfo,
+    path: Option<String>,
+) -> Result<Value, RpcError> {
+    let path = path
+        .or(tab.path)
+        .ok_or_else(|| RpcError::new(REQUEST_FAILED, "Untitled tab: give a path to save it to"))?;
+
+    let buffer = call_window(app, label, "buffer.read", json!({ "tabId": tab.tab_id }))
+        .map_err(|e| RpcError::new(REQUEST_FAILED, e))?;
+    let content = buffer
+        .get("content")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(REQUEST_FAILED, "Window sent no content"))?
+        .to_string();
+
+    let encoding = tab.encoding.as_deref().unwrap_or("UTF-8");
+    crate::write_file(
+        &app.state::<crate::BaseContentState>(),
+        path.clone(),
+        content.clone(),
+        encoding,
+    )
+    .map_err(|e| RpcError::new(REQUEST_FAILED, e))?;
+
+    // The file is saved either way; the tab only shows it
+    let saved = json!({ "tabId": tab.tab_id, "path": path, "content": content });
+    if let Err(e) = call_window(app, label, "file.saved", saved) {
+        eprintln!("Failed to mark tab as saved: {}", e);
+    }
+    Ok(json!({ "path": path }))
+}
+
+/// Open a file at an optional position, the way files given on the command
+/// line are opened. The file is queued for the window, so that it opens
+/// even if the window is busy; the tab id is returned once the tab shows up.
+fn open_file(app: &AppHandle, p: OpenParams) -> Result<Value, RpcError> {
+    if !Path::new(&p.path).is_absolute() {
+        return Err(RpcError::new(INVALID_PARAMS, "path must be absolute"));
+    }
+    if p.line == Some(0) || p.column == Some(0) {
+        return Err(RpcError::new(INVALID_PARAMS, "line and column start at 1"));
+    }
+    if p.column.is_some() && p.line.is_none() {
+        return Err(RpcError::new(INVALID_PARAMS, "column requires line"));
+    }
+
+    let window = match p.window {
+        Some(label) => app.get_webview_window(&label),
+        None => crate::target_window(app),
+    }
+    .ok_or_else(|| RpcError::new(REQUEST_FAILED, "No such window"))?;
+
+    let file = cli::CliFile {
+        path: p.path.clone(),
+        line: p.line,
+        column: p.column,
+        ..cli::CliFile::default()
+    };
+    let label = window.label().to_string();
+    crate::queue_open_files(
+        app,
+        &label,
+        crate::OpenFiles {
+            files: vec![file],
+            options: cli::OpenOptions::default(),
+            ignored_overrides: Vec::new(),
+        },
+    );
+    let _ = window.unminimize();
+    let _ = window.set_focus();
+
+    let windows = app.state::<WindowTabs>();
+    let started = Instant::now();
+    let tab_id = loop {
+        let tab = windows
+            .get(&label)
+            .into_iter()
+            .find(|tab| tab.active && tab.path.as_deref() == Some(p.path.as_str()));
+        match tab {
+            Some(tab) => break Value::String(tab.tab_id),
+            // Opened once the window gets to it
+            None if started.elapsed() >= FRONTEND_TIMEOUT => break Value::Null,
+            None => std::thread::sleep(OPEN_POLL_INTERVAL),
+        }
+    };
+
+    Ok(json!({ "window": label, "tabId": tab_id }))
+}
+
+/// Pass a request to a window's frontend and wait for its answer
+fn call_window(app: &AppHandle, label: &str, method: &str, params: Value) -> Result<Value, String> {
+    if app.get_webview_window(label).is_none() {
+        return Err(format!("Unknown window '{}'", label));
+    }
+
+    let pending = app.state::<PendingRequests>();
+    let id = pending.next_id.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = mpsc::channel();
+    pending
+        .senders
+        .lock()
+        .map_err(|e| e.to_string())?
+        .insert(id, sender);
+
+    let request = FrontendRequest {
+        id,
+        method: method.to_string(),
+        params,
+    };
+    let result = app
+        .emit_to(label, "rpc-request", request)
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            receiver
+                .recv_timeout(FRONTEND_TIMEOUT)
+                .map_err(|_| format!("Window '{}' did not answer", label))
+        });
+
+    if let Ok(mut senders) = pending.senders.lock() {
+        senders.remove(&id);
+    }
+    result?
+}
+
+/// Call a method of the running instance, for `velt rpc`
+#[cfg(unix)]
+pub fn call(method: &str, params: Value) -> Result<Value, String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| format!("Velt is not running ({}: {})", path.display(), e))?;
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writeln!(stream, "{}", request).map_err(|e| e.to_string())?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let response: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+
+    match response.get("error") {
+        Some(error) => Err(format!(
+            "{} ({})",
+            error["message"].as_str().unwrap_or("Unknown error"),
+            error["code"]
+        )),
+        None => Ok(response.get("result").cloned().unwrap_or(Value::Null)),
+    }
+}
@@ -0,0 +1,417 @@
+use crate::config;
+use crate::fs_util::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+
+/// Bump when the session format changes incompatibly; older sessions are discarded
+const SESSION_VERSION: u32 = 1;
+
+// Named session selected with `--session NAME`; `None` is the default session
+static ACTIVE_SESSION: Mutex<Option<String>> = Mutex::new(None);
+
+/// Lock on the named session's lock file, held until the process exits
+static SESSION_LOCK: OnceLock<fs::File> = OnceLock::new();
+
+/// Locked by the instance using a session, so that other processes can tell
+/// it is in use. The OS releases it if the instance crashes.
+const LOCK_FILE: &str = "session.lock";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ViewState {
+    pub line: usize,
+    pub column: usize,
+    #[serde(rename = "scrollTop", default)]
+    pub scroll_top: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionTab {
+    pub id: String,
+    #[serde(rename = "filePath")]
+    pub file_path: Option<String>,
+    pub encoding: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(rename = "isPreview", default)]
+    pub is_preview: bool,
+    #[serde(
+        rename = "sourceTabId",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub source_tab_id: Option<String>,
+    #[serde(rename = "noteMode", default)]
+    pub note_mode: bool,
+    /// Unsaved contents are kept in the buffer store (hot exit)
+    #[serde(rename = "hotExit", default)]
+    pub hot_exit: bool,
+    #[serde(rename = "viewState", default, skip_serializing_if = "Option::is_none")]
+    pub view_state: Option<ViewState>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PaneSession {
+    #[serde(rename = "tabIds")]
+    pub tab_ids: Vec<String>,
+    #[serde(rename = "activeTabId")]
+    pub active_tab_id: Option<String>,
+}
+
+/// Everything needed to restore one window: its tabs and the pane layout
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WindowSession {
+    pub tabs: Vec<SessionTab>,
+    pub left: PaneSession,
+    pub right: Option<PaneSession>,
+    #[serde(rename = "focusedPane")]
+    pub focused_pane: String,
+    #[serde(rename = "activeTabId")]
+    pub active_tab_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub version: u32,
+    /// Workspace folders open in the session
+    #[serde(default)]
+    pub roots: Vec<String>,
+    /// Window sessions keyed by window label
+    pub windows: BTreeMap<String, WindowSession>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            version: SESSION_VERSION,
+            roots: Vec::new(),
+            windows: BTreeMap::new(),
+        }
+    }
+}
+
+/// Summary of a named session, for listing
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionInfo {
+    pub name: String,
+    pub roots: Vec<String>,
+    #[serde(rename = "windowCount")]
+    pub window_count: usize,
+    #[serde(rename = "tabCount")]
+    pub tab_count: usize,
+    /// Last save, in seconds since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+/// Select the named session used by this process (`None` for the default one)
+pub fn set_active_session(name: Option<&str>) -> Result<(), String> {
+    if let Some(name) = name {
+        validate_session_name(name)?;
+    }
+    let mut active = ACTIVE_SESSION.lock().map_err(|e| e.to_string())?;
+    *active = name.map(str::to_string);
+    Ok(())
+}
+
+pub fn get_active_session() -> Option<String> {
+    ACTIVE_SESSION.lock().ok()?.clone()
+}
+
+/// Whether a running instance holds the lock of the active session
+pub fn active_session_in_use() -> bool {
+    let Ok(dir) = get_session_dir() else {
+        return false;
+    };
+    match fs::File::open(dir.join(LOCK_FILE)) {
+        Ok(file) => matches!(file.try_lock(), Err(fs::TryLockError::WouldBlock)),
+        Err(_) => false,
+    }
+}
+
+/// Mark the active session as in use by this process until it exits
+pub fn lock_active_session() -> Result<(), String> {
+    if SESSION_LOCK.get().is_some() {
+        return Ok(());
+    }
+
+    let dir = get_session_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))
+        .map_err(|e| e.to_string())?;
+    file.try_lock().map_err(|e| match e {
+        fs::TryLockError::WouldBlock => "Session is in use by another instance".to_string(),
+        fs::TryLockError::Error(e) => e.to_string(),
+    })?;
+    let _ = SESSION_LOCK.set(file);
+    Ok(())
+}
+
+/// Session names become directory names: keep them to a safe character set
+pub fn validate_session_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid session name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        ))
+    }
+}
+
+fn get_sessions_dir() -> Result<PathBuf, String> {
+    Ok(config::get_state_dir()?.join("sessions"))
+}
+
+/// Directory holding a session file and its buffers. The default session
+/// lives directly in the state directory, named ones in `sessions/<name>/`.
+fn get_session_dir() -> Result<PathBuf, String> {
+    match get_active_session() {
+        Some(name) => Ok(get_sessions_dir()?.join(name)),
+        None => config::get_state_dir(),
+    }
+}
+
+/// Get the session file path of the active session
+pub fn get_session_path() -> Result<PathBuf, String> {
+    Ok(get_session_dir()?.join("session.json"))
+}
+
+/// Load the saved session. A missing, unreadable or outdated session is
+/// treated as empty: losing the layout must never prevent startup.
+pub fn load_session() -> Result<Session, String> {
+    let path = get_session_path()?;
+    if !path.exists() {
+        return Ok(Session::default());
+    }
+
+    Ok(read_session_file(&path).unwrap_or_default())
+}
+
+fn read_session_file(path: &Path) -> Option<Session> {
+    let content = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<Session>(&content) {
+        Ok(session) if session.version == SESSION_VERSION => Some(session),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("Ignoring invalid session file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn save_session(session: &Session) -> Result<(), String> {
+    let path = get_session_path()?;
+    let json = serde_json::to_string_pretty(session).map_err(|e| e.to_string())?;
+    write_atomic(&path, json.as_bytes())
+}
+
+/// Store the session of one window, replacing its previous state, along with
+/// the workspace folders currently open
+pub fn save_window_session(
+    label: &str,
+    window: WindowSession,
+    roots: Vec<String>,
+) -> Result<(), String> {
+    let mut session = load_session()?;
+    session.windows.insert(label.to_string(), window);
+    session.roots = roots;
+    save_session(&session)
+}
+
+pub fn load_window_session(label: &str) -> Result<Option<WindowSession>, String> {
+    Ok(load_session()?.windows.remove(label))
+}
+
+/// Keep only the tabs with unsaved contents, for when session restore is
+/// turned off: unsaved work is still never lost
+pub fn hot_exit_only(mut window: WindowSession) -> Option<WindowSession> {
+    window.tabs.retain(|tab| tab.hot_exit);
+    if window.tabs.is_empty() {
+        return None;
+    }
+
+    let kept: Vec<String> = window.tabs.iter().map(|tab| tab.id.clone()).collect();
+    let retain_pane = |pane: &mut PaneSession| {
+        pane.tab_ids.retain(|id| kept.contains(id));
+        if pane
+            .active_tab_id
+            .as_ref()
+            .is_some_and(|id| !kept.contains(id))
+        {
+            pane.active_tab_id = pane.tab_ids.first().cloned();
+        }
+    };
+    retain_pane(&mut window.left);
+    if let Some(right) = window.right.as_mut() {
+        retain_pane(right);
+    }
+    if window
+        .active_tab_id
+        .as_ref()
+        .is_some_and(|id| !kept.contains(id))
+    {
+        window.active_tab_id = None;
+    }
+    Some(window)
+}
+
+/// Forget a window, e.g. when the user closes it while others stay open
+pub fn remove_window_session(label: &str) -> Result<(), String> {
+    let mut session = load_session()?;
+    if session.windows.remove(label).is_some() {
+        save_session(&session)?;
+        prune_buffers(&session)?;
+    }
+    Ok(())
+}
+
+/// Named sessions, sorted by name
+pub fn list_sessions() -> Result<Vec<SessionInfo>, String> {
+    let dir = get_sessions_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sessions = Vec::new();
+    let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path().join("session.json");
+        if validate_session_name(&name).is_err() || !path.is_file() {
+            continue;
+        }
+
+        let session = read_session_file(&path).unwrap_or_default();
+        let modified = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        sessions.push(SessionInfo {
+            name,
+            roots: session.roots,
+            window_count: session.windows.len(),
+            tab_count: session.windows.values().map(|w| w.tabs.len()).sum(),
+            modified,
+        });
+    }
+
+    sessions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(sessions)
+}
+
+/// Delete a named session with its unsaved buffers, unless a running
+/// instance (this one or another) uses it
+pub fn delete_session(name: &str) -> Result<(), String> {
+    validate_session_name(name)?;
+    let in_use = || format!("Session '{}' is in use", name);
+    if get_active_session().as_deref() == Some(name) {
+        return Err(in_use());
+    }
+
+    let dir = get_sessions_dir()?.join(name);
+    if !dir.join("session.json").exists() {
+        return Err(format!("Session '{}' does not exist", name));
+    }
+
+    // Held while deleting, so that no instance can start using it meanwhile
+    let lock = match fs::File::open(dir.join(LOCK_FILE)) {
+        Ok(file) => match file.try_lock() {
+            Ok(()) => Some(file),
+            Err(fs::TryLockError::WouldBlock) => return Err(in_use()),
+            Err(fs::TryLockError::Error(e)) => {
+                return Err(format!("Failed to delete session: {}", e))
+            }
+        },
+        Err(_) => None,
+    };
+    // Windows cannot remove a file that is open
+    #[cfg(windows)]
+    drop(lock);
+
+    let result = fs::remove_dir_all(&dir).map_err(|e| format!("Failed to delete session: {}", e));
+    #[cfg(not(windows))]
+    drop(lock);
+    result
+}
+
+fn get_buffers_dir() -> Result<PathBuf, String> {
+    Ok(get_session_dir()?.join("buffers"))
+}
+
+/// Buffer file of a tab. Contents are stored as plain UTF-8 text, one file per
+/// tab, so large buffers need no escaping; the tab's encoding lives in the
+/// session and is applied when the buffer is eventually saved.
+fn buffer_path(tab_id: &str) -> Result<PathBuf, String> {
+    let valid = !tab_id.is_empty()
+        && tab_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid tab id: '{}'", tab_id));
+    }
+    Ok(get_buffers_dir()?.join(format!("{}.txt", tab_id)))
+}
+
+/// Store the unsaved contents of a tab
+pub fn store_buffer(tab_id: &str, content: &str) -> Result<(), String> {
+    write_atomic(&buffer_path(tab_id)?, content.as_bytes())
+}
+
+pub fn load_buffer(tab_id: &str) -> Result<Option<String>, String> {
+    let path = buffer_path(tab_id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path)
+        .map(Some)
+        .map_err(|e| format!("Failed to read unsaved buffer: {}", e))
+}
+
+/// Drop the stored contents of a tab once it is saved or discarded
+pub fn discard_buffer(tab_id: &str) -> Result<(), String> {
+    let path = buffer_path(tab_id)?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Delete stored buffers no longer referenced by any window of the session
+pub fn prune_buffers(session: &Session) -> Result<(), String> {
+    let dir = get_buffers_dir()?;
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    let referenced: Vec<String> = session
+        .windows
+        .values()
+        .flat_map(|window| window.tabs.iter())
+        .filter(|tab| tab.hot_exit)
+        .map(|tab| format!("{}.txt", tab.id))
+        .collect();
+
+    let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !referenced.contains(&name) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    Ok(())
+}
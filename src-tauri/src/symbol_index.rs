@@ -0,0 +1,402 @@
+use crate::config;
+use crate::outline::{self, DocumentSymbol, OutlineLanguage};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Bump when the on-disk index format changes, to force a full rebuild
+const INDEX_VERSION: u32 = 1;
+
+/// Files larger than this are not indexed (generated or minified sources)
+const MAX_INDEXED_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Changes are saved once the workspace has been quiet for this long...
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+/// ...or after this long at the latest, during a long stream of changes
+const MAX_SAVE_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexedSymbol {
+    pub name: String,
+    pub kind: String,
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    /// Name of the enclosing symbol (e.g. the impl or class of a method)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SymbolMatch {
+    #[serde(flatten)]
+    pub symbol: IndexedSymbol,
+    pub score: i64,
+    /// Character positions in `name` matched by the query, for highlighting
+    pub indices: Vec<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct IndexedFile {
+    modified: u64,
+    size: u64,
+    symbols: Vec<IndexedSymbol>,
+}
+
+/// Symbols of every supported source file below a workspace root
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SymbolIndex {
+    version: u32,
+    root: PathBuf,
+    files: HashMap<String, IndexedFile>,
+}
+
+/// An index kept up to date by a file watcher for as long as it is alive.
+/// Pending changes are saved when it is dropped.
+pub struct WatchedIndex {
+    pub index: Arc<Mutex<SymbolIndex>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl SymbolIndex {
+    /// Load the persisted index of `root` (if any) and bring it up to date with the
+    /// files on disk, re-parsing only files whose size or modification time changed
+    pub fn open(root: &Path) -> Result<Self, String> {
+        let mut index = Self::load(root).unwrap_or_else(|| Self {
+            version: INDEX_VERSION,
+            root: root.to_path_buf(),
+            files: HashMap::new(),
+        });
+
+        let mut previous = std::mem::take(&mut index.files);
+
+        for entry in WalkBuilder::new(root).build() {
+            let Ok(entry) = entry else { continue };
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let path = entry.path();
+            if index_language(path).is_none() {
+                continue;
+            }
+
+            let key = path.to_string_lossy().to_string();
+            let cached = previous.remove(&key);
+            let Some((modified, size)) = file_stamp(path) else {
+                continue;
+            };
+
+            let file = match cached {
+                Some(file) if file.modified == modified && file.size == size => Some(file),
+                _ => index_file(path),
+            };
+            if let Some(file) = file {
+                index.files.insert(key, file);
+            }
+        }
+
+        index.save()?;
+        Ok(index)
+    }
+
+    /// Re-index a file or directory, or drop it (and anything below it) if it no longer exists
+    pub fn update_path(&mut self, path: &Path) {
+        let key = path.to_string_lossy().to_string();
+
+        if path.is_dir() {
+            for entry in WalkBuilder::new(path).build().flatten() {
+                if entry.file_type().is_some_and(|t| t.is_file()) {
+                    self.update_path(entry.path());
+                }
+            }
+        } else if path.is_file() {
+            match index_file(path) {
+                Some(file) => {
+                    self.files.insert(key, file);
+                }
+                None => {
+                    self.files.remove(&key);
+                }
+            }
+        } else if !path.exists() {
+            let prefix = format!("{}{}", key, std::path::MAIN_SEPARATOR);
+            self.files
+                .retain(|file, _| file != &key && !file.starts_with(&prefix));
+        }
+    }
+
+    /// Fuzzy search symbol names, best matches first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SymbolMatch> {
+        let matcher = SkimMatcherV2::default();
+        let mut matches: Vec<SymbolMatch> = self
+            .symbols()
+            .filter_map(|symbol| {
+                let (score, indices) = matcher.fuzzy_indices(&symbol.name, query)?;
+                Some(SymbolMatch {
+                    symbol: symbol.clone(),
+                    score,
+                    indices,
+                })
+            })
+            .collect();
+
+        matches.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.symbol.name.len().cmp(&b.symbol.name.len()))
+                .then_with(|| a.symbol.path.cmp(&b.symbol.path))
+        });
+        matches.truncate(limit);
+        matches
+    }
+
+    /// All symbols named exactly `name`, for "go to definition"
+    pub fn definitions(&self, name: &str) -> Vec<IndexedSymbol> {
+        let mut definitions: Vec<IndexedSymbol> = self
+            .symbols()
+            .filter(|symbol| symbol.name == name)
+            .cloned()
+            .collect();
+        definitions.sort_by(|a, b| a.path.cmp(&b.path).then(a.line.cmp(&b.line)));
+        definitions
+    }
+
+    fn symbols(&self) -> impl Iterator<Item = &IndexedSymbol> {
+        self.files.values().flat_map(|file| file.symbols.iter())
+    }
+
+    fn load(root: &Path) -> Option<Self> {
+        let content = fs::read_to_string(index_path(root).ok()?).ok()?;
+        let index: Self = serde_json::from_str(&content).ok()?;
+        (index.version == INDEX_VERSION && index.root == root).then_some(index)
+    }
+
+    /// Persist the index to the state directory
+    pub fn save(&self) -> Result<(), String> {
+        let path = index_path(&self.root)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(&path, json).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// Open the index of `root` and start watching the workspace for changes
+pub fn watch(root: &Path) -> Result<WatchedIndex, String> {
+    let index = Arc::new(Mutex::new(SymbolIndex::open(root)?));
+    let mut gitignores = GitignoreCache::new(root);
+
+    // A checkout or a build changes thousands of files: save in batches
+    let (changed, changes) = mpsc::channel();
+    let saved = Arc::clone(&index);
+    std::thread::spawn(move || save_when_quiet(&saved, &changes));
+
+    let watched = Arc::clone(&index);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else { return };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+
+        for path in &event.paths {
+            gitignores.forget_changed(path);
+        }
+        let paths: Vec<&PathBuf> = event
+            .paths
+            .iter()
+            .filter(|path| !gitignores.is_ignored(path))
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+
+        if let Ok(mut index) = watched.lock() {
+            for path in paths {
+                if path.is_dir() || index_language(path).is_some() || !path.exists() {
+                    index.update_path(path);
+                }
+            }
+        }
+        let _ = changed.send(());
+    })
+    .map_err(|e| format!("Failed to start file watcher: {}", e))?;
+
+    watcher
+        .watch(root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch '{}': {}", root.display(), e))?;
+
+    Ok(WatchedIndex {
+        index,
+        _watcher: watcher,
+    })
+}
+
+/// Save the index after changes, until the watcher (and with it the sender
+/// of `changes`) is dropped
+fn save_when_quiet(index: &Mutex<SymbolIndex>, changes: &mpsc::Receiver<()>) {
+    while changes.recv().is_ok() {
+        let first_change = Instant::now();
+        loop {
+            match changes.recv_timeout(SAVE_DELAY) {
+                Ok(()) if first_change.elapsed() < MAX_SAVE_DELAY => continue,
+                // Disconnected: the index was closed, save what is pending
+                Ok(()) | Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        if let Ok(index) = index.lock() {
+            if let Err(e) = index.save() {
+                eprintln!("Failed to save symbol index: {}", e);
+            }
+        }
+    }
+}
+
+/// Language used to index a file; structured data keys are left out of the workspace index
+fn index_language(path: &Path) -> Option<OutlineLanguage> {
+    OutlineLanguage::from_path(path)
+        .filter(|lang| !matches!(lang, OutlineLanguage::Json | OutlineLanguage::Yaml))
+}
+
+fn index_file(path: &Path) -> Option<IndexedFile> {
+    let language = index_language(path)?;
+    let (modified, size) = file_stamp(path)?;
+    if size > MAX_INDEXED_FILE_SIZE {
+        return None;
+    }
+
+    let bytes = fs::read(path).ok()?;
+    let source = String::from_utf8_lossy(&bytes);
+    let outline = outline::document_outline(&source, language).ok()?;
+
+    let mut symbols = Vec::new();
+    flatten(&outline, None, &path.to_string_lossy(), &mut symbols);
+
+    Some(IndexedFile {
+        modified,
+        size,
+        symbols,
+    })
+}
+
+fn flatten(
+    outline: &[DocumentSymbol],
+    container: Option<&str>,
+    path: &str,
+    out: &mut Vec<IndexedSymbol>,
+) {
+    for symbol in outline {
+        out.push(IndexedSymbol {
+            name: symbol.name.clone(),
+            kind: symbol.kind.clone(),
+            path: path.to_string(),
+            line: symbol.selection_range.start_line,
+            column: symbol.selection_range.start_column,
+            container: container.map(str::to_string),
+        });
+        flatten(&symbol.children, Some(&symbol.name), path, out);
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((modified, metadata.len()))
+}
+
+/// Ignore files of a workspace, read as watcher events need them
+struct GitignoreCache {
+    root: PathBuf,
+    dirs: HashMap<PathBuf, Gitignore>,
+}
+
+impl GitignoreCache {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Mirror the walker's filtering for watcher events: hidden entries, and
+    /// paths ignored by the `.gitignore` or `.ignore` of any folder above them
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        let hidden = rel
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        if hidden {
+            return true;
+        }
+
+        // The deepest ignore file that has a say wins, as with git
+        let is_dir = path.is_dir();
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) {
+                break;
+            }
+            let matched = self
+                .ignore_files(dir)
+                .matched_path_or_any_parents(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn ignore_files(&mut self, dir: &Path) -> &Gitignore {
+        let root = &self.root;
+        self.dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+            let mut builder = GitignoreBuilder::new(dir);
+            if dir == root {
+                builder.add(root.join(".git").join("info").join("exclude"));
+            }
+            // Later files take precedence, as `.ignore` does for the walker
+            builder.add(dir.join(".gitignore"));
+            builder.add(dir.join(".ignore"));
+            builder.build().unwrap_or_else(|_| Gitignore::empty())
+        })
+    }
+
+    /// Re-read an ignore file the next time it is needed, once it changed
+    fn forget_changed(&mut self, path: &Path) {
+        let is_ignore_file = path
+            .file_name()
+            .is_some_and(|name| name == ".gitignore" || name == ".ignore");
+        if let (true, Some(dir)) = (is_ignore_file, path.parent()) {
+            self.dirs.remove(dir);
+        }
+    }
+}
+
+/// Index file location: one file per workspace root, named after a hash of the root path
+fn index_path(root: &Path) -> Result<PathBuf, String> {
+    let digest = Sha256::digest(root.to_string_lossy().as_bytes());
+    let name: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(config::get_state_dir()?
+        .join("symbols")
+        .join(format!("{}.json", name)))
+}
@@ -0,0 +1,17 @@
+import type { VeltEditor } from '@altagen/velt-core';
+import type { PaneId } from '../stores/paneStore';
+
+// Editor shown in each pane, for code that acts on the text outside of it
+const editors = new Map<PaneId, VeltEditor>();
+
+export function registerEditor(paneId: PaneId, editor: VeltEditor) {
+  editors.set(paneId, editor);
+}
+
+export function unregisterEditor(paneId: PaneId, editor: VeltEditor) {
+  if (editors.get(paneId) === editor) editors.delete(paneId);
+}
+
+export function getEditor(paneId: PaneId): VeltEditor | undefined {
+  return editors.get(paneId);
+}
@@ -0,0 +1,187 @@
+import { invoke } from '@tauri-apps/api/core';
+import { get } from 'svelte/store';
+import type { Tab } from '@altagen/velt-core';
+import { tabs, updateTabEncoding, updateTabLanguage } from '../stores/appStore';
+import { noteModeSet, addNoteTab } from '../stores/noteModeStore';
+import {
+  viewStates,
+  editorMarks,
+  setPendingViewState,
+  setPendingMarks,
+  type ViewState,
+  type FoldRange,
+} from '../stores/viewStateStore';
+
+/**
+ * What is remembered about a file between openings
+ */
+export interface FileState {
+  viewState?: ViewState;
+  folds?: FoldRange[];
+  bookmarks?: number[];
+  encoding?: string;
+  language?: string;
+  noteMode: boolean;
+}
+
+const SAVE_DELAY = 1000;
+
+// Tabs whose file state was already looked up, and the path it was saved under
+const knownTabs = new Map<string, string>();
+// Last state written per tab, to skip saves when nothing changed
+const savedStates = new Map<string, string>();
+// Latest state of each open file tab, kept so closed tabs can still be saved
+const snapshots = new Map<string, { path: string; state: FileState }>();
+let saveTimer: ReturnType<typeof setTimeout> | null = null;
+// Tabs opened with an explicit position, encoding or language (e.g. on the command line)
+const explicitTabs = new Set<string>();
+
+export async function getFileState(path: string): Promise<FileState | null> {
+  return await invoke<FileState | null>('get_file_state', { path });
+}
+
+export async function setFileState(path: string, fileState: FileState): Promise<void> {
+  await invoke('set_file_state', { path, fileState });
+}
+
+/**
+ * Keep what a tab was explicitly opened with: only remembered marks are applied to it.
+ * Must be called before the tab is added.
+ */
+export function markExplicitlyOpened(tabId: string) {
+  explicitTabs.add(tabId);
+}
+
+function isFileTab(tab: Tab): tab is Tab & { filePath: string } {
+  return tab.filePath !== null && !tab.isPreview;
+}
+
+/**
+ * Apply the remembered state of a newly opened file. Tabs restored from a
+ * session or opened explicitly already carry their own cursor and overrides:
+ * only marks are applied.
+ */
+async function applyFileState(tab: Tab & { filePath: string }) {
+  const restored = get(viewStates)[tab.id] !== undefined || explicitTabs.delete(tab.id);
+
+  let state: FileState | null;
+  try {
+    state = await getFileState(tab.filePath);
+  } catch (error) {
+    console.error('Failed to load file state:', error);
+    return;
+  }
+  if (!state || !get(tabs).some(t => t.id === tab.id)) return;
+
+  setPendingMarks(tab.id, { folds: state.folds ?? [], bookmarks: state.bookmarks ?? [] });
+  if (restored) return;
+
+  // The cursor may have moved while the state was loading
+  if (state.viewState && get(viewStates)[tab.id] === undefined) {
+    setPendingViewState(tab.id, state.viewState);
+  }
+  if (state.encoding && state.encoding !== tab.encoding) {
+    updateTabEncoding(tab.id, state.encoding);
+  }
+  if (state.language && !tab.language) {
+    updateTabLanguage(tab.id, state.language);
+  }
+  if (state.noteMode) {
+    addNoteTab(tab.id);
+  }
+}
+
+function takeSnapshots() {
+  const noteTabs = get(noteModeSet);
+  const states = get(viewStates);
+  const marks = get(editorMarks);
+
+  for (const tab of get(tabs)) {
+    // Only once looked up, so a new tab never overwrites what is remembered
+    if (!isFileTab(tab) || !knownTabs.has(tab.id)) continue;
+    snapshots.set(tab.id, {
+      path: tab.filePath,
+      state: {
+        viewState: states[tab.id],
+        folds: marks[tab.id]?.folds,
+        bookmarks: marks[tab.id]?.bookmarks,
+        encoding: tab.encoding,
+        language: tab.language,
+        noteMode: noteTabs.has(tab.id),
+      },
+    });
+  }
+}
+
+async function saveSnapshot(tabId: string) {
+  const snapshot = snapshots.get(tabId);
+  if (!snapshot) return;
+
+  const json = JSON.stringify(snapshot);
+  if (savedStates.get(tabId) === json) return;
+  savedStates.set(tabId, json);
+
+  try {
+    await setFileState(snapshot.path, snapshot.state);
+  } catch (error) {
+    console.error('Failed to save file state:', error);
+  }
+}
+
+/**
+ * Save the state of every open file now, e.g. before the window closes
+ */
+export async function flushFileStates(): Promise<void> {
+  if (saveTimer) clearTimeout(saveTimer);
+  saveTimer = null;
+  takeSnapshots();
+  await Promise.all([...snapshots.keys()].map(saveSnapshot));
+}
+
+/**
+ * Restore the remembered state of files as they are opened, and remember
+ * it again as it changes and when their tabs are closed
+ */
+export function startFileStateSync(): () => void {
+  const scheduleSave = () => {
+    takeSnapshots();
+    if (saveTimer) clearTimeout(saveTimer);
+    saveTimer = setTimeout(flushFileStates, SAVE_DELAY);
+  };
+
+  const unsubscribers = [
+    tabs.subscribe($tabs => {
+      const open = new Set($tabs.map(t => t.id));
+
+      // Closed tabs: save their last known state, then forget them
+      for (const tabId of [...knownTabs.keys()]) {
+        if (open.has(tabId)) continue;
+        saveSnapshot(tabId);
+        knownTabs.delete(tabId);
+        snapshots.delete(tabId);
+        savedStates.delete(tabId);
+      }
+
+      for (const tab of $tabs) {
+        if (!isFileTab(tab)) continue;
+        // Look up newly opened files, and tabs saved under a new name
+        if (knownTabs.get(tab.id) !== tab.filePath) {
+          const isNew = !knownTabs.has(tab.id);
+          knownTabs.set(tab.id, tab.filePath);
+          if (isNew) applyFileState(tab);
+        }
+      }
+
+      scheduleSave();
+    }),
+    noteModeSet.subscribe(scheduleSave),
+    viewStates.subscribe(scheduleSave),
+    editorMarks.subscribe(scheduleSave),
+  ];
+
+  return () => {
+    unsubscribers.forEach(unsubscribe => unsubscribe());
+    if (saveTimer) clearTimeout(saveTimer);
+    saveTimer = null;
+  };
+}
@@ -0,0 +1,100 @@
+import { invoke } from '@tauri-apps/api/core';
+import type { Tab } from '@altagen/velt-core';
+import { tabs } from '../stores/appStore';
+
+const STORE_DELAY = 1000;
+
+// Contents last written to the buffer store, per tab
+const storedBuffers = new Map<string, string>();
+const pendingStores = new Map<string, ReturnType<typeof setTimeout>>();
+let latestTabs: Tab[] = [];
+
+/**
+ * Whether a tab's contents must survive a restart: unsaved changes,
+ * or an untitled buffer with something in it
+ */
+export function needsHotExit(tab: Tab): boolean {
+  if (tab.isPreview) return false;
+  return tab.isDirty || (tab.filePath === null && tab.content !== '');
+}
+
+/**
+ * Remember that a restored tab's contents are already in the store
+ */
+export function markBufferStored(tabId: string, content: string) {
+  storedBuffers.set(tabId, content);
+}
+
+export async function loadBuffer(tabId: string): Promise<string | null> {
+  return await invoke<string | null>('load_buffer', { tabId });
+}
+
+async function storeBuffer(tabId: string) {
+  pendingStores.delete(tabId);
+  const tab = latestTabs.find(t => t.id === tabId);
+  if (!tab || !needsHotExit(tab) || storedBuffers.get(tabId) === tab.content) return;
+
+  try {
+    await invoke('store_buffer', { tabId, content: tab.content });
+    storedBuffers.set(tabId, tab.content);
+  } catch (error) {
+    console.error('Failed to store unsaved buffer:', error);
+  }
+}
+
+async function discardBuffer(tabId: string) {
+  const pending = pendingStores.get(tabId);
+  if (pending) clearTimeout(pending);
+  pendingStores.delete(tabId);
+
+  if (!storedBuffers.has(tabId)) return;
+  storedBuffers.delete(tabId);
+  try {
+    await invoke('discard_buffer', { tabId });
+  } catch (error) {
+    console.error('Failed to discard unsaved buffer:', error);
+  }
+}
+
+/**
+ * Keep the buffer store in sync with the tabs: store unsaved contents
+ * shortly after each edit, and drop them once saved or closed
+ */
+export function startHotExit(): () => void {
+  const unsubscribe = tabs.subscribe($tabs => {
+    latestTabs = $tabs;
+    const open = new Set($tabs.map(t => t.id));
+
+    for (const tab of $tabs) {
+      if (!needsHotExit(tab)) {
+        discardBuffer(tab.id);
+      } else if (storedBuffers.get(tab.id) !== tab.content) {
+        const pending = pendingStores.get(tab.id);
+        if (pending) clearTimeout(pending);
+        pendingStores.set(tab.id, setTimeout(() => storeBuffer(tab.id), STORE_DELAY));
+      }
+    }
+
+    for (const tabId of [...storedBuffers.keys(), ...pendingStores.keys()]) {
+      if (!open.has(tabId)) discardBuffer(tabId);
+    }
+  });
+
+  return () => {
+    unsubscribe();
+    for (const pending of pendingStores.values()) clearTimeout(pending);
+    pendingStores.clear();
+  };
+}
+
+/**
+ * Write all pending buffers immediately, before the window closes
+ */
+export async function flushBuffers(): Promise<void> {
+  const tabIds = [...pendingStores.keys()];
+  for (const tabId of tabIds) {
+    const pending = pendingStores.get(tabId);
+    if (pending) clearTimeout(pending);
+  }
+  await Promise.all(tabIds.map(storeBuffer));
+}
@@ -0,0 +1,129 @@
+import { invoke } from '@tauri-apps/api/core';
+import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
+import type { UnlistenFn } from '@tauri-apps/api/event';
+import { get, derived } from 'svelte/store';
+import { tick } from 'svelte';
+import type { Tab } from '@altagen/velt-core';
+import { tabs, activeTabId, getTab, updateTabContent, saveTab, updateTabFile } from '../stores/appStore';
+import { getPaneForTab, setActiveTab } from '../stores/paneStore';
+import { getEditor } from './editorRegistry';
+import { readOnlySet } from '../stores/readOnlyStore';
+
+interface RpcRequest {
+  id: number;
+  method: string;
+  params: any;
+}
+
+// The tabs of this window as the backend lists and finds them (see rpc.rs)
+const tabInfos = derived([tabs, activeTabId, readOnlySet], ([$tabs, $activeTabId, $readOnlySet]) =>
+  $tabs
+    .filter(t => !t.isPreview)
+    .map(t => ({
+      tabId: t.id,
+      path: t.filePath,
+      dirty: t.isDirty,
+      active: t.id === $activeTabId,
+      encoding: t.encoding,
+      language: t.language ?? null,
+      readOnly: $readOnlySet.has(t.id),
+    })),
+);
+
+function findTab(tabId: string): Tab {
+  const tab = getTab(tabId);
+  if (!tab || tab.isPreview) throw new Error('Unknown tab');
+  return tab;
+}
+
+// The backend already refuses read-only tabs (`velt --readonly`); checked
+// again in case the tab became read-only since it last heard of it
+function findWritableTab(tabId: string): Tab {
+  const tab = findTab(tabId);
+  if (get(readOnlySet).has(tab.id)) throw new Error('Tab is read-only');
+  return tab;
+}
+
+function requireString(params: any, name: string): string {
+  if (typeof params[name] !== 'string') throw new Error(`${name} must be a string`);
+  return params[name];
+}
+
+// Editor showing the tab, bringing the tab to the front of its pane if needed
+async function editorFor(tab: Tab) {
+  const pane = getPaneForTab(tab.id);
+  if (!pane) throw new Error('Tab is not shown in a pane');
+  setActiveTab(pane, tab.id);
+  await tick();
+  const editor = getEditor(pane);
+  if (!editor) throw new Error('Tab is not shown in an editor');
+  return editor;
+}
+
+// Only what lives in this window is asked of it: the text of the tabs and
+// the cursor. Tabs are found, and files written, by the backend.
+async function handle(method: string, params: any): Promise<unknown> {
+  switch (method) {
+    case 'buffer.read': {
+      const tab = findTab(params.tabId);
+      return { content: tab.content, path: tab.filePath, dirty: tab.isDirty, encoding: tab.encoding };
+    }
+    case 'buffer.replace': {
+      const tab = findWritableTab(params.tabId);
+      updateTabContent(tab.id, requireString(params, 'content'));
+      return null;
+    }
+    case 'buffer.insert': {
+      const tab = findWritableTab(params.tabId);
+      const text = requireString(params, 'text');
+      const view = (await editorFor(tab)).getView();
+      view.dispatch(view.state.replaceSelection(text), { scrollIntoView: true });
+      return null;
+    }
+    case 'file.saved': {
+      const tab = findTab(params.tabId);
+      const path: string = requireString(params, 'path');
+      // Edited since its text was read for saving: stays unsaved
+      if (tab.content !== params.content) return null;
+      if (path === tab.filePath) {
+        saveTab(tab.id);
+      } else {
+        updateTabFile(tab.id, path, tab.content, tab.encoding);
+      }
+      return null;
+    }
+    default:
+      throw new Error(`Unknown method '${method}'`);
+  }
+}
+
+/**
+ * Keep the backend up to date with the tabs of this window, and answer the
+ * automation API requests it passes on (buffer text lives here, not in the backend).
+ */
+export async function startRpcHandler(): Promise<UnlistenFn> {
+  let synced = '';
+  const unsubscribe = tabInfos.subscribe(list => {
+    // Typing changes the tabs store on every key; the list rarely changes
+    const json = JSON.stringify(list);
+    if (json === synced) return;
+    synced = json;
+    invoke('sync_tabs', { tabs: list }).catch(error => console.error('Failed to sync tabs:', error));
+  });
+
+  const unlisten = await getCurrentWebviewWindow().listen<RpcRequest>('rpc-request', async event => {
+    const { id, method, params } = event.payload;
+    try {
+      const result = await handle(method, params ?? {});
+      await invoke('rpc_respond', { id, result });
+    } catch (error) {
+      const message = error instanceof Error ? error.message : String(error);
+      await invoke('rpc_respond', { id, error: message }).catch(console.error);
+    }
+  });
+
+  return () => {
+    unsubscribe();
+    unlisten();
+  };
+}
@@ -0,0 +1,248 @@
+import { invoke } from '@tauri-apps/api/core';
+import { get } from 'svelte/store';
+import type { Tab } from '@altagen/velt-core';
+import { tabs, createTab, createPreviewTab } from '../stores/appStore';
+import { paneLayout, setActiveTab, type PaneId, type PaneState } from '../stores/paneStore';
+import { noteModeSet, addNoteTab } from '../stores/noteModeStore';
+import { viewStates, setPendingViewState, type ViewState } from '../stores/viewStateStore';
+import type { FileContent } from './fileOperations';
+import { needsHotExit, loadBuffer, markBufferStored } from './hotExit';
+
+export interface SessionTab {
+  id: string;
+  filePath: string | null;
+  encoding: string;
+  language?: string;
+  isPreview: boolean;
+  sourceTabId?: string;
+  noteMode: boolean;
+  hotExit: boolean;
+  viewState?: ViewState;
+}
+
+export interface WindowSession {
+  tabs: SessionTab[];
+  left: PaneState;
+  right: PaneState | null;
+  focusedPane: PaneId;
+  activeTabId: string | null;
+}
+
+const SAVE_DELAY = 500;
+
+let saveTimer: ReturnType<typeof setTimeout> | null = null;
+
+/**
+ * Snapshot the tabs and pane layout of this window
+ */
+export function collectWindowSession(): WindowSession {
+  const noteTabs = get(noteModeSet);
+  const states = get(viewStates);
+  const layout = get(paneLayout);
+
+  // Empty untitled tabs have nothing worth reopening
+  const sessionTabs: SessionTab[] = get(tabs)
+    .filter(tab => tab.filePath !== null || needsHotExit(tab))
+    .map(tab => ({
+      id: tab.id,
+      filePath: tab.filePath,
+      encoding: tab.encoding,
+      language: tab.language,
+      isPreview: tab.isPreview ?? false,
+      sourceTabId: tab.sourceTabId,
+      noteMode: noteTabs.has(tab.id),
+      hotExit: needsHotExit(tab),
+      viewState: states[tab.id],
+    }));
+
+  const kept = new Set(sessionTabs.map(tab => tab.id));
+  const keepPane = (pane: PaneState): PaneState => ({
+    tabIds: pane.tabIds.filter(id => kept.has(id)),
+    activeTabId: pane.activeTabId && kept.has(pane.activeTabId) ? pane.activeTabId : null,
+  });
+
+  const focused = layout.focusedPane === 'right' && layout.right ? layout.right : layout.left;
+
+  return {
+    tabs: sessionTabs,
+    left: keepPane(layout.left),
+    right: layout.right ? keepPane(layout.right) : null,
+    focusedPane: layout.focusedPane,
+    activeTabId: focused.activeTabId && kept.has(focused.activeTabId) ? focused.activeTabId : null,
+  };
+}
+
+/**
+ * Reopen the tabs of this window's previous session.
+ * Returns true if at least one tab was restored.
+ */
+export async function restoreWindowSession(): Promise<boolean> {
+  let session: WindowSession | null;
+  try {
+    session = await invoke<WindowSession | null>('load_window_session');
+  } catch (error) {
+    console.error('Failed to load session:', error);
+    return false;
+  }
+  if (!session || session.tabs.length === 0) return false;
+
+  const restored = new Map<string, Tab>();
+
+  // Files first, so preview tabs can find their source
+  for (const saved of session.tabs.filter(t => !t.isPreview)) {
+    const tab = await restoreTab(saved);
+    if (tab) restored.set(saved.id, tab);
+  }
+
+  for (const saved of session.tabs.filter(t => t.isPreview)) {
+    const source = saved.sourceTabId ? restored.get(saved.sourceTabId) : undefined;
+    if (source) {
+      restored.set(saved.id, { ...createPreviewTab(source), id: saved.id });
+    }
+  }
+
+  if (restored.size === 0) return false;
+
+  tabs.set(session.tabs.map(t => restored.get(t.id)).filter((t): t is Tab => t !== undefined));
+
+  for (const saved of session.tabs) {
+    if (!restored.has(saved.id)) continue;
+    if (saved.noteMode) addNoteTab(saved.id);
+    if (saved.viewState) setPendingViewState(saved.id, saved.viewState);
+  }
+
+  const restorePane = (pane: PaneState): PaneState => {
+    const tabIds = pane.tabIds.filter(id => restored.has(id));
+    const activeTabId = pane.activeTabId && restored.has(pane.activeTabId)
+      ? pane.activeTabId
+      : tabIds[0] ?? null;
+    return { tabIds, activeTabId };
+  };
+
+  let left = restorePane(session.left);
+  let right = session.right ? restorePane(session.right) : null;
+  if (right && right.tabIds.length === 0) right = null;
+
+  // Tabs missing from both panes (should not happen) end up on the left
+  const placed = new Set([...left.tabIds, ...(right?.tabIds ?? [])]);
+  const orphans = [...restored.keys()].filter(id => !placed.has(id));
+  if (orphans.length > 0) {
+    left = { tabIds: [...left.tabIds, ...orphans], activeTabId: left.activeTabId ?? orphans[0] };
+  }
+
+  const focusedPane: PaneId = session.focusedPane === 'right' && right ? 'right' : 'left';
+  paneLayout.set({ left, right, focusedPane });
+
+  const focused = focusedPane === 'right' && right ? right : left;
+  if (focused.activeTabId) {
+    setActiveTab(focusedPane, focused.activeTabId);
+  }
+
+  return true;
+}
+
+async function restoreTab(saved: SessionTab): Promise<Tab | null> {
+  let buffer: string | null = null;
+  if (saved.hotExit) {
+    try {
+      buffer = await loadBuffer(saved.id);
+    } catch (error) {
+      console.error('Failed to load unsaved buffer:', error);
+    }
+  }
+
+  if (saved.filePath === null) {
+    if (buffer === null) return null;
+    // Untitled buffers come back still dirty, with nothing on disk
+    const tab = { ...createTab(null, ''), id: saved.id, content: buffer, isDirty: true };
+    tab.encoding = saved.encoding;
+    if (saved.language) tab.language = saved.language;
+    markBufferStored(saved.id, buffer);
+    return tab;
+  }
+
+  let tab: Tab;
+  try {
+    const fileContent = await invoke<FileContent>('read_file_content', { path: saved.filePath });
+    tab = { ...createTab(fileContent.path, fileContent.content), id: saved.id };
+    // Keep an encoding the user picked for saving over the detected one
+    tab.encoding = saved.encoding || fileContent.encoding;
+  } catch (error) {
+    if (buffer === null) {
+      // The file was moved or deleted since the last session
+      console.warn(`Skipping ${saved.filePath} from previous session:`, error);
+      return null;
+    }
+    // Unsaved edits of a file that is gone: keep them, saving recreates the file
+    tab = { ...createTab(saved.filePath, ''), id: saved.id };
+    tab.encoding = saved.encoding;
+  }
+
+  if (saved.language) tab.language = saved.language;
+  if (buffer !== null) {
+    tab.content = buffer;
+    tab.isDirty = buffer !== tab.originalContent;
+    markBufferStored(saved.id, buffer);
+  }
+  return tab;
+}
+
+/**
+ * Save this window's session whenever its tabs, layout or positions change
+ */
+export function startSessionAutoSave(): () => void {
+  const scheduleSave = () => {
+    if (saveTimer) clearTimeout(saveTimer);
+    saveTimer = setTimeout(saveWindowSession, SAVE_DELAY);
+  };
+
+  const unsubscribers = [
+    tabs.subscribe(scheduleSave),
+    paneLayout.subscribe(scheduleSave),
+    noteModeSet.subscribe(scheduleSave),
+    viewStates.subscribe(scheduleSave),
+  ];
+
+  return () => {
+    unsubscribers.forEach(unsubscribe => unsubscribe());
+    if (saveTimer) clearTimeout(saveTimer);
+    saveTimer = null;
+  };
+}
+
+export async function saveWindowSession(): Promise<void> {
+  try {
+    await invoke('save_window_session', { session: collectWindowSession() });
+  } catch (error) {
+    console.error('Failed to save session:', error);
+  }
+}
+
+export interface SessionInfo {
+  name: string;
+  roots: string[];
+  windowCount: number;
+  tabCount: number;
+  modified: number | null;
+}
+
+/**
+ * Named sessions (`velt --session NAME`), sorted by name
+ */
+export async function listSessions(): Promise<SessionInfo[]> {
+  return await invoke<SessionInfo[]>('list_sessions');
+}
+
+/**
+ * Delete a named session; the session this instance runs in cannot be deleted
+ */
+export async function deleteSession(name: string): Promise<void> {
+  await invoke('delete_session', { name });
+}
+
+/**
+ * Name of the session this instance runs in, or null for the default session
+ */
+export async function getActiveSession(): Promise<string | null> {
+  return await invoke<string | null>('get_active_session');
+}
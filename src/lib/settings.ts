@@ -0,0 +1,174 @@
+import { get } from 'svelte/store';
+import { message } from '@tauri-apps/plugin-dialog';
+import { settings, defaultSettings } from '../stores/appStore';
+import { lockedSettings } from '../stores/lockedSettingsStore';
+import {
+  getConfig,
+  updateConfig,
+  onConfigChanged,
+  getLockedSettings,
+  getConfigSources,
+  type AppConfig,
+} from './theme';
+import type { AppSettings } from '../types';
+
+// Where settings were kept before they moved to the config
+const LEGACY_KEY = 'velt-settings';
+
+function fromConfig(config: AppConfig): AppSettings {
+  return {
+    showMenuIcons: config.showMenuIcons,
+    autoSave: config.autoSave,
+    autoSaveDelay: config.autoSaveDelay,
+    fontSize: config.fontSize,
+    fontFamily: config.fontFamily ?? defaultSettings.fontFamily,
+    tabSize: config.tabSize,
+    wordWrap: config.wordWrap,
+    showInvisibles: config.showInvisibles,
+    autoIndent: config.autoIndent,
+    zoomLevel: config.zoomLevel,
+  };
+}
+
+// The config keys of the settings, leaving the rest of the config alone
+function toConfig(value: AppSettings): Partial<AppConfig> {
+  return {
+    showMenuIcons: value.showMenuIcons,
+    autoSave: value.autoSave,
+    autoSaveDelay: value.autoSaveDelay,
+    fontSize: value.fontSize,
+    // Keep following the platform default unless another font was picked
+    fontFamily: value.fontFamily === defaultSettings.fontFamily ? null : value.fontFamily,
+    tabSize: value.tabSize,
+    wordWrap: value.wordWrap,
+    showInvisibles: value.showInvisibles,
+    autoIndent: value.autoIndent,
+    zoomLevel: value.zoomLevel,
+  };
+}
+
+/**
+ * Take over the settings stored in localStorage by earlier versions, once.
+ * Settings locked by the system policy keep their enforced values. Each
+ * setting is moved on its own, so that one invalid value does not lose the rest.
+ */
+async function migrateLegacySettings(config: AppConfig): Promise<AppConfig> {
+  const stored = localStorage.getItem(LEGACY_KEY);
+  if (!stored) return config;
+
+  try {
+    const legacy = JSON.parse(stored);
+    const locked = get(lockedSettings);
+    const keys = Object.keys(fromConfig(config)) as (keyof AppSettings)[];
+    for (const key of keys) {
+      if (!(key in legacy) || locked.includes(key)) continue;
+
+      const value = toConfig({ ...fromConfig(config), [key]: legacy[key] })[key];
+      try {
+        config = await updateConfig({ [key]: value });
+      } catch (e) {
+        console.error(`Dropped the stored setting '${key}':`, e);
+      }
+    }
+  } catch (e) {
+    console.error('Failed to move settings from localStorage to the config:', e);
+  }
+  // Dropped even when invalid, so a bad value is not retried on every start
+  localStorage.removeItem(LEGACY_KEY);
+  return config;
+}
+
+/**
+ * Tell the user about saved changes that have no effect, because the
+ * workspace settings or a command line override take precedence
+ */
+async function reportShadowedChanges(changes: Partial<AppConfig>, config: AppConfig): Promise<void> {
+  const keys = (Object.keys(changes) as (keyof AppConfig)[]).filter(
+    key => JSON.stringify(changes[key]) !== JSON.stringify(config[key]),
+  );
+  if (keys.length === 0) return;
+
+  const sources = await getConfigSources();
+  const reasons = keys.map(key => {
+    const source = sources.find(s => s.key === key);
+    const where = source?.layer === 'cli'
+      ? 'the --set option Velt was started with'
+      : `the workspace settings (${source?.path ?? '.velt/settings.json'})`;
+    return `'${key}' is overridden by ${where}`;
+  });
+  await message(
+    `Your change was saved, but does not apply here: ${reasons.join('; ')}.`,
+    { title: 'Setting Overridden', kind: 'warning' },
+  );
+}
+
+/**
+ * Load the settings from the config into the settings store
+ */
+export async function loadSettings(): Promise<void> {
+  lockedSettings.set(await getLockedSettings());
+  const config = await migrateLegacySettings(await getConfig());
+  settings.set(fromConfig(config));
+}
+
+/**
+ * Save changes of the settings store to the config, one at a time, and
+ * follow changes made in other windows. Changes to locked settings (from the
+ * status bar or a shortcut, say) are undone. Returns a function to stop.
+ */
+export async function startSettingsSync(): Promise<() => void> {
+  // The settings as in the config
+  let saved = get(settings);
+  let saving = Promise.resolve();
+
+  // Marked as saved first, so the change is not written back
+  function showSaved(next: AppSettings) {
+    saved = next;
+    settings.set(next);
+  }
+
+  const unsubscribe = settings.subscribe(value => {
+    const keys = (Object.keys(value) as (keyof AppSettings)[]).filter(key => value[key] !== saved[key]);
+    if (keys.length === 0) return;
+
+    const locked = get(lockedSettings);
+    const lockedKeys = keys.filter(key => locked.includes(key));
+    if (lockedKeys.length > 0) {
+      const enforced = Object.fromEntries(lockedKeys.map(key => [key, saved[key]]));
+      settings.set({ ...value, ...enforced });
+      return;
+    }
+
+    saved = value;
+    const config = toConfig(value);
+    const changes = Object.fromEntries(keys.map(key => [key, config[key]]));
+    saving = saving.then(async () => {
+      try {
+        const config = await updateConfig(changes);
+        if (keys.some(key => JSON.stringify(fromConfig(config)[key]) !== JSON.stringify(value[key]))) {
+          // Show what is in effect, and why
+          showSaved(fromConfig(config));
+          await reportShadowedChanges(changes, config);
+        }
+      } catch (e) {
+        console.error('Failed to save settings:', e);
+        // Show what is in effect rather than the change that was refused
+        try {
+          showSaved(fromConfig(await getConfig()));
+        } catch (e) {
+          console.error('Failed to reload settings:', e);
+        }
+      }
+    });
+  });
+
+  const unlisten = await onConfigChanged(config => {
+    const next = fromConfig(config);
+    if (JSON.stringify(next) !== JSON.stringify(saved)) showSaved(next);
+  });
+
+  return () => {
+    unsubscribe();
+    unlisten();
+  };
+}
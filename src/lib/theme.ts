@@ -1,12 +1,23 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import type { Theme } from '@altagen/velt-core';
 
 export interface AppConfig {
+  version: number;
   theme: string;
   themesDir: string | null;
   autoSave: boolean;
   autoSaveDelay: number;
   recentFiles: string[];
+  restoreSession: boolean;
+  showMenuIcons: boolean;
+  fontSize: number;
+  fontFamily: string | null; // null: the platform's monospace font
+  tabSize: number;
+  wordWrap: boolean;
+  showInvisibles: boolean;
+  autoIndent: boolean;
+  zoomLevel: number;
 }
 
 /**
@@ -17,10 +28,39 @@ export async function getConfig(): Promise<AppConfig> {
 }
 
 /**
- * Save the application configuration
+ * Change some settings, keeping changes other windows made to the rest
  */
-export async function saveConfig(config: AppConfig): Promise<void> {
-  await invoke('save_app_config', { configData: config });
+export async function updateConfig(changes: Partial<AppConfig>): Promise<AppConfig> {
+  return await invoke<AppConfig>('update_app_config', { changes });
+}
+
+/**
+ * Call back with the new config whenever any window changes it
+ */
+export async function onConfigChanged(callback: (config: AppConfig) => void): Promise<UnlistenFn> {
+  return await listen<AppConfig>('config-changed', event => callback(event.payload));
+}
+
+export interface ConfigValueSource {
+  key: string;
+  value: unknown;
+  layer: 'default' | 'system' | 'user' | 'workspace' | 'cli' | 'policy';
+  path: string | null; // none for defaults and command line overrides
+  locked: boolean;
+}
+
+/**
+ * Get every effective config value with the layer it came from
+ */
+export async function getConfigSources(): Promise<ConfigValueSource[]> {
+  return await invoke<ConfigValueSource[]>('get_config_sources');
+}
+
+/**
+ * Get the settings locked by the system policy (these cannot be changed)
+ */
+export async function getLockedSettings(): Promise<string[]> {
+  return await invoke<string[]>('get_locked_settings');
 }
 
 /**
@@ -55,7 +95,5 @@ export async function loadCurrentTheme(): Promise<Theme> {
  * Set the current theme and save config
  */
 export async function setCurrentTheme(themeName: string): Promise<void> {
-  const config = await getConfig();
-  config.theme = themeName;
-  await saveConfig(config);
+  await updateConfig({ theme: themeName });
 }
@@ -0,0 +1,45 @@
+import { invoke } from '@tauri-apps/api/core';
+import { tabs } from '../stores/appStore';
+
+interface Waiting {
+  tabIds: Set<string>;
+  // Tab holding piped input, whose final text is passed back to the invocation
+  outputTabId?: string;
+  output?: string;
+}
+
+// What each waiting `velt --wait` invocation is waiting on
+const waiting = new Map<string, Waiting>();
+
+async function release(token: string, output?: string) {
+  waiting.delete(token);
+  try {
+    await invoke('release_wait', { token, output });
+  } catch (error) {
+    console.error('Failed to release waiting invocation:', error);
+  }
+}
+
+/**
+ * Let a `velt --wait` invocation return once all the given tabs are closed.
+ * Without tabs, it returns when the window closes (handled by the backend).
+ */
+export function waitForTabs(token: string, tabIds: string[], outputTabId?: string) {
+  if (tabIds.length === 0) return;
+  waiting.set(token, { tabIds: new Set(tabIds), outputTabId });
+}
+
+tabs.subscribe($tabs => {
+  if (waiting.size === 0) return;
+
+  const open = new Map($tabs.map(t => [t.id, t]));
+  for (const [token, wait] of waiting) {
+    // Keep the latest text: the tab is gone by the time it is closed
+    const outputTab = wait.outputTabId ? open.get(wait.outputTabId) : undefined;
+    if (outputTab) wait.output = outputTab.content;
+
+    if (![...wait.tabIds].some(id => open.has(id))) {
+      release(token, wait.output);
+    }
+  }
+});
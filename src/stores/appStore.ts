@@ -2,10 +2,9 @@ import { writable, get } from 'svelte/store';
 import type { Tab, AppSettings } from '../types';
 import { getDefaultMonospaceFont } from '../lib/systemFonts';
 
-// Default settings
+// Default settings, until the config is loaded (see lib/settings.ts)
 export const defaultSettings: AppSettings = {
   showMenuIcons: true,
-  currentTheme: 'Dark',
   autoSave: true,
   autoSaveDelay: 1000,
   fontSize: 14,
@@ -132,6 +131,34 @@ export function updateTabEncoding(tabId: string, encoding: string) {
   );
 }
 
+export function updateTabLanguage(tabId: string, language: string | undefined) {
+  tabs.update(t =>
+    t.map(tab =>
+      tab.id === tabId
+        ? { ...tab, language }
+        : tab
+    )
+  );
+}
+
+// Point tabs at a file's new location after it (or a parent folder) was renamed or moved
+export function updateTabPaths(from: string, to: string) {
+  tabs.update(t =>
+    t.map(tab => {
+      if (!tab.filePath) return tab;
+      if (tab.filePath === from) {
+        return { ...tab, filePath: to };
+      }
+      for (const sep of ['/', '\\']) {
+        if (tab.filePath.startsWith(from + sep)) {
+          return { ...tab, filePath: to + tab.filePath.slice(from.length) };
+        }
+      }
+      return tab;
+    })
+  );
+}
+
 export function getTab(tabId: string): Tab | undefined {
   let result: Tab | undefined;
   tabs.subscribe(t => {
@@ -0,0 +1,6 @@
+import { writable } from 'svelte/store';
+
+// Config keys locked by the system policy; the settings UI shows them disabled
+export const lockedSettings = writable<string[]>([]);
+
+export const LOCKED_TITLE = 'Locked by your administrator';
@@ -0,0 +1,31 @@
+import { writable, derived } from 'svelte/store';
+import { tabs } from './appStore';
+
+const readOnlyTabs = writable<Set<string>>(new Set());
+
+// Readable set of tab IDs opened read-only (e.g. with `velt --readonly`)
+export const readOnlySet = derived(readOnlyTabs, $s => $s);
+
+export function addReadOnlyTab(tabId: string) {
+  readOnlyTabs.update(set => {
+    const next = new Set(set);
+    next.add(tabId);
+    return next;
+  });
+}
+
+// Auto-cleanup: when a tab is removed, purge its read-only state
+tabs.subscribe($tabs => {
+  const tabIds = new Set($tabs.map(t => t.id));
+  readOnlyTabs.update(set => {
+    let changed = false;
+    const next = new Set(set);
+    for (const id of next) {
+      if (!tabIds.has(id)) {
+        next.delete(id);
+        changed = true;
+      }
+    }
+    return changed ? next : set;
+  });
+});
@@ -0,0 +1,90 @@
+import { writable, get, type Writable } from 'svelte/store';
+import { tabs } from './appStore';
+
+export interface ViewState {
+  line: number;
+  column: number;
+  scrollTop: number;
+}
+
+export interface FoldRange {
+  from: number;
+  to: number;
+}
+
+// Folded regions and bookmarked lines (1-based) of an editor
+export interface EditorMarks {
+  folds: FoldRange[];
+  bookmarks: number[];
+}
+
+// A cursor position asked for explicitly, e.g. `velt file:line:col` (1-based)
+export interface CursorPosition {
+  line: number;
+  column: number;
+}
+
+// What an editor should apply once it is ready (restored sessions, remembered file state)
+export interface PendingEditorState {
+  viewState?: ViewState;
+  marks?: EditorMarks;
+  position?: CursorPosition;
+}
+
+// Last known cursor and scroll position of each tab's editor
+export const viewStates = writable<Record<string, ViewState>>({});
+
+// Last known folds and bookmarks of each tab's editor
+export const editorMarks = writable<Record<string, EditorMarks>>({});
+
+export const pendingEditorStates = writable<Record<string, PendingEditorState>>({});
+
+export function recordViewState(tabId: string, state: ViewState) {
+  viewStates.update(states => ({ ...states, [tabId]: state }));
+}
+
+export function recordEditorMarks(tabId: string, marks: EditorMarks) {
+  editorMarks.update(all => ({ ...all, [tabId]: marks }));
+}
+
+export function setPendingViewState(tabId: string, state: ViewState) {
+  pendingEditorStates.update(all => ({ ...all, [tabId]: { ...all[tabId], viewState: state } }));
+  recordViewState(tabId, state);
+}
+
+export function setPendingMarks(tabId: string, marks: EditorMarks) {
+  pendingEditorStates.update(all => ({ ...all, [tabId]: { ...all[tabId], marks } }));
+  recordEditorMarks(tabId, marks);
+}
+
+export function setPendingPosition(tabId: string, position: CursorPosition) {
+  pendingEditorStates.update(all => ({ ...all, [tabId]: { ...all[tabId], position } }));
+}
+
+export function takePendingEditorState(tabId: string): PendingEditorState | undefined {
+  const pending = get(pendingEditorStates)[tabId];
+  if (!pending) return undefined;
+
+  pendingEditorStates.update(all => {
+    const { [tabId]: _, ...rest } = all;
+    return rest;
+  });
+  return pending;
+}
+
+function removeClosedTabs<T>(store: Writable<Record<string, T>>, tabIds: Set<string>) {
+  const entries = get(store);
+  if (Object.keys(entries).every(id => tabIds.has(id))) return;
+
+  store.set(Object.fromEntries(
+    Object.entries(entries).filter(([id]) => tabIds.has(id))
+  ));
+}
+
+// Auto-cleanup: forget positions and marks of closed tabs
+tabs.subscribe($tabs => {
+  const tabIds = new Set($tabs.map(t => t.id));
+  removeClosedTabs(viewStates, tabIds);
+  removeClosedTabs(editorMarks, tabIds);
+  removeClosedTabs(pendingEditorStates, tabIds);
+});
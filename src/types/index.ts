@@ -4,7 +4,6 @@ export type { Tab, Theme } from '@altagen/velt-core';
 // Velt-specific types
 export interface AppSettings {
   showMenuIcons: boolean;
-  currentTheme: string;
   autoSave: boolean;
   autoSaveDelay: number;
   fontSize: number;
@@ -15,3 +14,40 @@ export interface AppSettings {
   autoIndent: boolean;
   zoomLevel: number; // 100 = 100%
 }
+
+// A file given to `velt`, with the position to open it at (1-based)
+export interface CliFile {
+  path: string;
+  line?: number;
+  column?: number;
+  // Given as a velt:// link to a file outside the known workspaces
+  confirmOpen?: boolean;
+}
+
+// A corrupt config.json the backend moved aside at startup
+export interface ConfigRecovery {
+  backupPath: string;
+  error: string;
+  line: number | null;
+  column: number | null;
+  // Settings dropped for having the wrong type; empty when the whole file was replaced
+  droppedSettings: string[];
+}
+
+// Options a `velt` invocation passes along with its files
+export interface OpenOptions {
+  waitToken?: string;
+  readonly?: boolean;
+  encoding?: string;
+  language?: string;
+  // Text piped to `velt -`, opened as an untitled buffer
+  stdin?: { content: string; encoding: string };
+}
+
+// Files forwarded by a later `velt` invocation to this window
+export interface ForwardedFiles {
+  files: CliFile[];
+  options: OpenOptions;
+  // `--set` overrides of the invocation, ignored by the running instance
+  ignoredOverrides?: string[];
+}